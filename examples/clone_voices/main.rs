@@ -1,8 +1,12 @@
 //! `cargo run --example clone_voices -- "/path/to/voice.m4a" "audio/x-m4a"`
 use playht_rs::{
-    api::{self, voice::CloneVoiceFileRequest, voice::DeleteClonedVoiceRequest},
+    api::{
+        self,
+        voice::{CloneVoiceFileRequest, DeleteClonedVoiceRequest, UploadOpts},
+    },
     prelude::*,
 };
+use std::sync::Arc;
 use tokio;
 
 #[tokio::main]
@@ -19,7 +23,13 @@ async fn main() -> Result<()> {
 
     let client = api::Client::new();
 
-    let voice = client.clone_voice_from_file(&req).await?;
+    let opts = UploadOpts {
+        progress: Some(Arc::new(|p| {
+            println!("Uploaded {}/{} bytes", p.sent, p.total)
+        })),
+        ..Default::default()
+    };
+    let voice = client.clone_voice_from_file_with_opts(&req, &opts).await?;
     println!("Got voice clone: {:?}", voice);
 
     let cloned_voices = client.get_cloned_voices().await?;
@@ -18,7 +18,7 @@ async fn main() -> Result<()> {
     if voices.is_empty() {
         return Err("No voices available for playback".into());
     }
-    let client = api::Client::new();
+
     let req = TTSStreamReq {
         text: Some("What is life?".to_owned()),
         voice: Some(voices[0].id.to_owned()),
@@ -0,0 +1,54 @@
+//! `cargo run --example tts_stream_audio_input -- "foobar.mp3"`
+use playht_rs::{
+    api::{
+        self,
+        stream::{TTSInputStreamOpts, TTSStreamReq},
+        tts::Quality,
+    },
+    prelude::*,
+};
+use tokio::{fs::File, io::AsyncWriteExt, io::BufWriter};
+use tokio_stream::StreamExt;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let file_path = args.next().unwrap();
+
+    let client = api::Client::new();
+    let voices = client.get_stock_voices().await?;
+    if voices.is_empty() {
+        return Err("No voices available".into());
+    }
+
+    let req = TTSStreamReq {
+        voice: Some(voices[0].id.to_owned()),
+        quality: Some(Quality::Low),
+        speed: Some(1.0),
+        sample_rate: Some(24000),
+        ..Default::default()
+    };
+    let (tx, mut stream) = client.stream_audio_input(&req, &TTSInputStreamOpts::default())?;
+
+    // NOTE: the text fragments would normally be produced by an LLM.
+    tokio::spawn(async move {
+        for token in [
+            "What ", "is ", "life? ", "Life ", "is ", "what ", "happens.",
+        ] {
+            if tx.send(token).is_err() {
+                return;
+            }
+        }
+        tx.close();
+    });
+
+    let file = File::create(file_path.clone()).await?;
+    let mut w = BufWriter::new(file);
+    while let Some(chunk) = stream.next().await {
+        w.write_all(&chunk?).await?;
+    }
+    w.flush().await?;
+    println!("Done streaming into {}", file_path);
+
+    Ok(())
+}
@@ -0,0 +1,163 @@
+//! module for resolving the API credentials from secret stores.
+//!
+//! Implement the [`CredentialProvider`] trait to load the API credentials
+//! from a secret store, such as Vault or AWS SSM, and pass it to
+//! [`crate::api::ClientBuilder::credential_provider`]. The client keeps the provider
+//! around so that it can reload the credentials once they've been rotated;
+//! see [`crate::api::Client::refresh_credentials`].
+//!
+//! NOTE: the credentials are only refreshed when [`crate::api::Client::refresh_credentials`]
+//! is called; the client neither tracks the expiry of the credentials nor refreshes them
+//! when the API rejects them. Call it periodically if your secret store issues short-lived
+//! credentials, or when a request fails with the `401` status.
+//!
+//! Enable the `keyring` feature to read the credentials from the OS keychain
+//! with the [`KeyringProvider`]. The keychain is only read if you build the client
+//! with [`crate::api::ClientBuilder::from_keyring`] or pass the provider explicitly.
+
+use crate::{api::USER_ID_HEADER, error::Error, prelude::*};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use std::{fmt, future::Future, pin::Pin};
+
+/// Keyring service the credentials are stored under by default.
+#[cfg(feature = "keyring")]
+pub const DEFAULT_KEYRING_SERVICE: &str = "playht";
+/// Keyring entry which stores the API secret key.
+#[cfg(feature = "keyring")]
+pub const KEYRING_SECRET_KEY_ENTRY: &str = "PLAYHT_SECRET_KEY";
+/// Keyring entry which stores the user ID.
+#[cfg(feature = "keyring")]
+pub const KEYRING_USER_ID_ENTRY: &str = "PLAYHT_USER_ID";
+
+/// API credentials.
+#[derive(Clone)]
+pub struct Credentials {
+    pub secret_key: String,
+    pub user_id: String,
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("secret_key", &"<redacted>")
+            .field("user_id", &self.user_id)
+            .finish()
+    }
+}
+
+/// Future returned by [`CredentialProvider::credentials`].
+pub type CredentialsFuture<'a> = Pin<Box<dyn Future<Output = Result<Credentials>> + Send + 'a>>;
+
+/// Provides the API credentials.
+/// The provider is called when the client is being built and whenever
+/// its credentials are refreshed. It's async so that the credentials can be
+/// fetched from remote secret stores without blocking the runtime:
+///
+/// ```ignore
+/// impl CredentialProvider for VaultProvider {
+///     fn credentials(&self) -> CredentialsFuture<'_> {
+///         Box::pin(async move {
+///             let secret = self.vault.read("secret/playht").await?;
+///             Ok(Credentials {
+///                 secret_key: secret.secret_key,
+///                 user_id: secret.user_id,
+///             })
+///         })
+///     }
+/// }
+/// ```
+pub trait CredentialProvider: Send + Sync {
+    /// Returns the current API credentials.
+    fn credentials(&self) -> CredentialsFuture<'_>;
+}
+
+impl fmt::Debug for dyn CredentialProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CredentialProvider").finish_non_exhaustive()
+    }
+}
+
+/// Reads the API credentials from the OS keychain.
+/// The secret key and the user ID are stored as the [`KEYRING_SECRET_KEY_ENTRY`]
+/// and [`KEYRING_USER_ID_ENTRY`] entries of the keyring service, which is
+/// [`DEFAULT_KEYRING_SERVICE`] by default.
+/// The keychain is read on the blocking thread pool of the [`tokio`] runtime.
+#[cfg(feature = "keyring")]
+#[derive(Debug, Clone)]
+pub struct KeyringProvider {
+    service: String,
+}
+
+#[cfg(feature = "keyring")]
+impl KeyringProvider {
+    /// Creates a new provider which reads the credentials of the given keyring service.
+    pub fn new(service: impl Into<String>) -> Self {
+        Self {
+            service: service.into(),
+        }
+    }
+}
+
+/// Reads the given entry of the given keyring service.
+#[cfg(feature = "keyring")]
+fn keyring_entry(service: &str, entry: &str) -> Result<String> {
+    let secret = keyring::Entry::new(service, entry)
+        .and_then(|e| e.get_password())
+        .map_err(|e| {
+            Error::CredentialsError(format!(
+                "could not read {} of keyring service {}: {}",
+                entry, service, e
+            ))
+        })?;
+
+    Ok(secret)
+}
+
+#[cfg(feature = "keyring")]
+impl Default for KeyringProvider {
+    fn default() -> Self {
+        Self::new(DEFAULT_KEYRING_SERVICE)
+    }
+}
+
+#[cfg(feature = "keyring")]
+impl CredentialProvider for KeyringProvider {
+    fn credentials(&self) -> CredentialsFuture<'_> {
+        let service = self.service.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || {
+                Ok(Credentials {
+                    secret_key: keyring_entry(&service, KEYRING_SECRET_KEY_ENTRY)?,
+                    user_id: keyring_entry(&service, KEYRING_USER_ID_ENTRY)?,
+                })
+            })
+            .await?
+        })
+    }
+}
+
+/// Sets the API authentication headers to the given credentials.
+pub(crate) fn set_headers(headers: &mut HeaderMap, creds: &Credentials) -> Result<()> {
+    set_secret_key(headers, &creds.secret_key)?;
+    set_user_id(headers, &creds.user_id)
+}
+
+/// Sets the API secret key header.
+/// The header is marked as sensitive so it doesn't leak into debug output.
+pub(crate) fn set_secret_key(headers: &mut HeaderMap, secret_key: &str) -> Result<()> {
+    let mut value = HeaderValue::from_str(secret_key)
+        .map_err(|e| Error::CredentialsError(format!("invalid secret key: {}", e)))?;
+    value.set_sensitive(true);
+    headers.insert(AUTHORIZATION, value);
+
+    Ok(())
+}
+
+/// Sets the user ID header.
+pub(crate) fn set_user_id(headers: &mut HeaderMap, user_id: &str) -> Result<()> {
+    let value = HeaderValue::from_str(user_id)
+        .map_err(|e| Error::CredentialsError(format!("invalid user id: {}", e)))?;
+    headers.insert(USER_ID_HEADER, value);
+
+    Ok(())
+}
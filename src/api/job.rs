@@ -4,8 +4,9 @@
 //! of the async TTS jobs.
 
 use crate::{
-    api::tts::{Emotion, OutputFormat, Quality, VoiceEngine},
+    api::tts::{self, Emotion, OutputFormat, Quality, VoiceEngine},
     api::Client,
+    error::ValidationError,
     prelude::*,
 };
 use bytes::Bytes;
@@ -53,7 +54,7 @@ impl Default for TTSJobReq {
             quality: Some(Quality::default()),
             output_format: Some(OutputFormat::default()),
             voice_engine: Some(VoiceEngine::default()),
-            emotion: Some(Emotion::default()),
+            emotion: None,
             speed: None,
             temperature: None,
             sample_rate: None,
@@ -64,6 +65,25 @@ impl Default for TTSJobReq {
     }
 }
 
+impl TTSJobReq {
+    /// Validates the request and returns all the constraints it violates.
+    /// [`crate::api::Client`] validates the requests before sending them to the API.
+    pub fn validate(&self) -> std::result::Result<(), ValidationError> {
+        tts::validate(tts::Options {
+            text: self.text.as_deref(),
+            output_format: self.output_format.as_ref(),
+            voice_engine: self.voice_engine.as_ref(),
+            emotion: self.emotion.as_ref(),
+            sample_rate: self.sample_rate,
+            speed: self.speed,
+            temperature: self.temperature,
+            voice_guidance: self.voice_guidance,
+            style_guidance: self.style_guidance,
+            text_guidance: None,
+        })
+    }
+}
+
 /// TTS job output metadata.
 #[derive(Debug, Clone, Deserialize)]
 pub struct Output {
@@ -0,0 +1,406 @@
+//! module for journaling the completed TTS syntheses.
+//!
+//! The journal appends a JSON line per completed synthesis into
+//! the writer it has been created with. The journal lines can then be
+//! reconciled against the play.ht invoices.
+//! The journal is opt-in; see [`crate::api::ClientBuilder::journal`].
+//!
+//! The entries are written by a dedicated writer thread so that writing
+//! into the journal never blocks the async runtime. Failed writes are
+//! counted by [`Journal::failures`]. Call [`Journal::close`] before your
+//! application exits to make sure all the recorded entries have been written.
+//!
+//! NOTE: the async TTS jobs are journaled with the [`Outcome::Submitted`] outcome
+//! once they've been created; the journal does not track the completion of the jobs.
+
+use crate::{
+    api::{job::TTSJobReq, stream::TTSStreamReq, tts::VoiceEngine},
+    prelude::*,
+};
+use bytes::Bytes;
+use serde::Serialize;
+use std::{
+    fmt,
+    io::Write,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    task::{Context, Poll},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio_stream::Stream;
+
+/// Outcome of the synthesis.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// The async TTS job has been created but its synthesis might not have completed yet.
+    Submitted,
+}
+
+/// Journal entry recorded for every completed synthesis.
+#[derive(Debug, Clone, Serialize)]
+pub struct JournalEntry {
+    /// Unix timestamp in milliseconds of when the synthesis completed.
+    pub timestamp: u64,
+    /// Number of characters of the synthesized text.
+    pub characters: usize,
+    pub voice: Option<String>,
+    pub engine: Option<VoiceEngine>,
+    /// ID of the TTS job if the synthesis was done by an async job.
+    pub job_id: Option<String>,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JournalEntry {
+    fn new(text: Option<&str>, voice: Option<&str>, engine: Option<&VoiceEngine>) -> Self {
+        JournalEntry {
+            timestamp: 0,
+            characters: text.map_or(0, |t| t.chars().count()),
+            voice: voice.map(|v| v.to_string()),
+            engine: engine.cloned(),
+            job_id: None,
+            outcome: Outcome::Success,
+            error: None,
+        }
+    }
+
+    pub(crate) fn submitted(mut self, job_id: Option<String>) -> Self {
+        self.job_id = job_id;
+        self.outcome = Outcome::Submitted;
+        self
+    }
+
+    pub(crate) fn failed(mut self, err: impl fmt::Display) -> Self {
+        self.outcome = Outcome::Failure;
+        self.error = Some(err.to_string());
+        self
+    }
+}
+
+impl From<&TTSJobReq> for JournalEntry {
+    fn from(req: &TTSJobReq) -> Self {
+        JournalEntry::new(
+            req.text.as_deref(),
+            req.voice.as_deref(),
+            req.voice_engine.as_ref(),
+        )
+    }
+}
+
+impl From<&TTSStreamReq> for JournalEntry {
+    fn from(req: &TTSStreamReq) -> Self {
+        JournalEntry::new(
+            req.text.as_deref(),
+            req.voice.as_deref(),
+            req.voice_engine.as_ref(),
+        )
+    }
+}
+
+/// Appends [`JournalEntry`] lines in JSONL format into the underlying writer.
+/// The writer is owned by a dedicated thread which writes the recorded entries
+/// and flushes the writer after every entry. The thread exits once the journal
+/// is closed or dropped. Dropping the journal does not wait for the pending entries;
+/// they're written in the background unless the process exits first.
+pub struct Journal {
+    tx: Mutex<Option<mpsc::Sender<JournalEntry>>>,
+    writer: Mutex<Option<thread::JoinHandle<()>>>,
+    failures: Arc<AtomicU64>,
+}
+
+impl Journal {
+    /// Creates a new journal which writes into the given writer.
+    pub fn new<W: Write + Send + 'static>(w: W) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let failures = Arc::new(AtomicU64::new(0));
+        let writer = {
+            let failures = failures.clone();
+            thread::Builder::new()
+                .name("playht-journal".to_string())
+                .spawn(move || write_entries(w, rx, failures))
+                .ok()
+        };
+
+        Journal {
+            tx: Mutex::new(writer.as_ref().map(|_| tx)),
+            writer: Mutex::new(writer),
+            failures,
+        }
+    }
+
+    /// Timestamps the given entry and queues it for writing into the journal.
+    pub fn record(&self, mut entry: JournalEntry) {
+        entry.timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+
+        let tx = self.tx.lock().unwrap_or_else(|e| e.into_inner());
+        let sent = tx.as_ref().is_some_and(|tx| tx.send(entry).is_ok());
+        if !sent {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Closes the journal and waits until all the recorded entries have been written.
+    /// The entries recorded after the journal has been closed are counted as failed.
+    /// NOTE: this blocks the calling thread until the writer is done, so call it
+    /// via [`tokio::task::spawn_blocking`] from async code.
+    pub fn close(&self) {
+        drop(self.tx.lock().unwrap_or_else(|e| e.into_inner()).take());
+        let writer = self.writer.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(writer) = writer {
+            let _ = writer.join();
+        }
+    }
+
+    /// Returns the number of entries which could not be written into the journal.
+    /// NOTE: a non-zero value means the journal can no longer be fully reconciled.
+    pub fn failures(&self) -> u64 {
+        self.failures.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Journal")
+            .field("failures", &self.failures())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Writes the received entries into the given writer until the journal is closed or dropped.
+fn write_entries<W: Write>(mut w: W, rx: mpsc::Receiver<JournalEntry>, failures: Arc<AtomicU64>) {
+    for entry in rx {
+        let written = serde_json::to_vec(&entry)
+            .map_err(std::io::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                w.write_all(&line)?;
+                w.flush()?;
+                Ok(())
+            });
+        if written.is_err() {
+            failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Records the given entry into the journal if there is one.
+/// NOTE: journal write failures must not fail the synthesis which has already
+/// been completed and billed by the API; they're counted by [`Journal::failures`].
+pub(crate) fn record(journal: Option<&Arc<Journal>>, entry: JournalEntry) {
+    if let Some(journal) = journal {
+        journal.record(entry);
+    }
+}
+
+/// Audio stream which records the journal entry once the stream completes.
+/// If the stream is dropped before completing, the entry is recorded as failed.
+pub(crate) struct JournaledStream<S> {
+    stream: Pin<Box<S>>,
+    journal: Option<Arc<Journal>>,
+    entry: Option<JournalEntry>,
+}
+
+impl<S> JournaledStream<S> {
+    pub(crate) fn new(stream: S, journal: Option<Arc<Journal>>, entry: JournalEntry) -> Self {
+        JournaledStream {
+            stream: Box::pin(stream),
+            journal,
+            entry: Some(entry),
+        }
+    }
+}
+
+impl<S> Stream for JournaledStream<S>
+where
+    S: Stream<Item = StreamResult<Bytes>>,
+{
+    type Item = StreamResult<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.stream.as_mut().poll_next(cx);
+        match &poll {
+            Poll::Ready(None) => {
+                if let Some(entry) = self.entry.take() {
+                    record(self.journal.as_ref(), entry);
+                }
+            }
+            Poll::Ready(Some(Err(err))) => {
+                if let Some(entry) = self.entry.take() {
+                    record(self.journal.as_ref(), entry.failed(err));
+                }
+            }
+            _ => {}
+        }
+
+        poll
+    }
+}
+
+impl<S> Drop for JournaledStream<S> {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            record(
+                self.journal.as_ref(),
+                entry.failed("stream dropped before completion"),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    /// Writer which shares the written bytes with the test.
+    #[derive(Clone, Default)]
+    struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedWriter {
+        fn lines(&self) -> Vec<serde_json::Value> {
+            let buf = self.0.lock().unwrap();
+            String::from_utf8_lossy(&buf)
+                .lines()
+                .map(|l| serde_json::from_str(l).unwrap())
+                .collect()
+        }
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+            Err(std::io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn entry() -> JournalEntry {
+        JournalEntry::from(&TTSStreamReq {
+            text: Some("What is life?".to_string()),
+            voice: Some("voice".to_string()),
+            ..Default::default()
+        })
+    }
+
+    fn stream_error() -> reqwest::Error {
+        reqwest::Client::new().get("http://").build().unwrap_err()
+    }
+
+    #[test]
+    fn journal_writes_jsonl_lines() {
+        let w = SharedWriter::default();
+        let journal = Journal::new(w.clone());
+        journal.record(entry());
+        journal.record(entry().failed("boom"));
+        journal.record(entry().submitted(Some("job".to_string())));
+        journal.close();
+
+        let lines = w.lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["outcome"], "success");
+        assert_eq!(lines[0]["characters"], 13);
+        assert_eq!(lines[0]["voice"], "voice");
+        assert_eq!(lines[0]["engine"], "PlayHT2.0");
+        assert!(lines[0]["timestamp"].as_u64().unwrap() > 0);
+        assert!(lines[0].get("error").is_none());
+        assert_eq!(lines[1]["outcome"], "failure");
+        assert_eq!(lines[1]["error"], "boom");
+        assert_eq!(lines[2]["outcome"], "submitted");
+        assert_eq!(lines[2]["job_id"], "job");
+        assert_eq!(journal.failures(), 0);
+    }
+
+    #[test]
+    fn journal_counts_failures() {
+        let journal = Journal::new(FailingWriter);
+        journal.record(entry());
+        journal.record(entry());
+        journal.close();
+        assert_eq!(journal.failures(), 2);
+
+        journal.record(entry());
+        assert_eq!(journal.failures(), 3);
+    }
+
+    #[tokio::test]
+    async fn journaled_stream_success() {
+        let w = SharedWriter::default();
+        let journal = Arc::new(Journal::new(w.clone()));
+        let chunks = vec![Ok(Bytes::from("a")), Ok(Bytes::from("b"))];
+        let mut stream =
+            JournaledStream::new(tokio_stream::iter(chunks), Some(journal.clone()), entry());
+        while stream.next().await.is_some() {}
+        assert!(stream.next().await.is_none());
+        drop(stream);
+        journal.close();
+
+        let lines = w.lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["outcome"], "success");
+    }
+
+    #[tokio::test]
+    async fn journaled_stream_error() {
+        let w = SharedWriter::default();
+        let journal = Arc::new(Journal::new(w.clone()));
+        let chunks = vec![
+            Ok(Bytes::from("a")),
+            Err(stream_error()),
+            Ok(Bytes::from("b")),
+        ];
+        let mut stream =
+            JournaledStream::new(tokio_stream::iter(chunks), Some(journal.clone()), entry());
+        while stream.next().await.is_some() {}
+        drop(stream);
+        journal.close();
+
+        let lines = w.lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["outcome"], "failure");
+        assert!(lines[0]["error"]
+            .as_str()
+            .unwrap()
+            .contains("builder error"));
+    }
+
+    #[tokio::test]
+    async fn journaled_stream_dropped() {
+        let w = SharedWriter::default();
+        let journal = Arc::new(Journal::new(w.clone()));
+        let chunks = vec![Ok(Bytes::from("a")), Ok(Bytes::from("b"))];
+        let mut stream =
+            JournaledStream::new(tokio_stream::iter(chunks), Some(journal.clone()), entry());
+        assert!(stream.next().await.is_some());
+        drop(stream);
+        journal.close();
+
+        let lines = w.lines();
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["outcome"], "failure");
+        assert_eq!(lines[0]["error"], "stream dropped before completion");
+    }
+}
@@ -0,0 +1,312 @@
+//! module for collecting the client metrics.
+//!
+//! Implement the [`MetricsSink`] trait and pass it to [`crate::api::ClientBuilder::metrics`]
+//! to record the request latency, time to the first audio byte and the streamed
+//! bytes and chunks of all the streaming and TTS job methods of the client.
+//! The metrics are reported per endpoint which is the name of the [`crate::api::Client`]
+//! method which made the request, such as `stream_audio`.
+//!
+//! NOTE: the time to the first byte and the stream stats are only recorded for the
+//! successful responses, so that the API error bodies don't skew the audio metrics;
+//! only the latency is recorded for the failed ones.
+
+use crate::prelude::*;
+use bytes::Bytes;
+use reqwest::StatusCode;
+use std::{
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio_stream::Stream;
+
+/// Stats of a completed response stream.
+#[derive(Debug, Clone, Default)]
+pub struct StreamStats {
+    /// Total number of streamed bytes.
+    pub bytes: u64,
+    /// Total number of streamed chunks.
+    pub chunks: u64,
+    /// Time elapsed between sending the request and the end of the stream.
+    pub duration: Duration,
+}
+
+/// Records the client metrics.
+/// All the methods are no-op by default so you only need to implement those you're interested in.
+pub trait MetricsSink: Send + Sync {
+    /// Records the time elapsed between sending the request and receiving the response headers.
+    fn record_latency(&self, _endpoint: &'static str, _latency: Duration) {}
+
+    /// Records the time elapsed between sending the request and receiving the first response byte.
+    /// For the audio streams this is the time to the first audio byte.
+    fn record_ttfb(&self, _endpoint: &'static str, _ttfb: Duration) {}
+
+    /// Records the stats of the response stream once it's been fully consumed or dropped.
+    fn record_stream(&self, _endpoint: &'static str, _stats: StreamStats) {}
+}
+
+/// Optional metrics sink used by the client.
+#[derive(Clone, Default)]
+pub(crate) struct Metrics(Option<Arc<dyn MetricsSink>>);
+
+impl Metrics {
+    pub(crate) fn new(sink: Arc<dyn MetricsSink>) -> Self {
+        Metrics(Some(sink))
+    }
+
+    /// Starts measuring the request to the given endpoint.
+    pub(crate) fn meter(&self, endpoint: &'static str) -> Meter {
+        Meter {
+            sink: self.0.clone(),
+            endpoint,
+            start: Instant::now(),
+            stats: StreamStats::default(),
+            done: false,
+        }
+    }
+
+    /// Starts measuring the latency of the request to the given endpoint
+    /// which does not stream its response, so no stream stats are recorded.
+    pub(crate) fn latency_meter(&self, endpoint: &'static str) -> Meter {
+        let mut meter = self.meter(endpoint);
+        meter.done = true;
+
+        meter
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Metrics").field(&self.0.is_some()).finish()
+    }
+}
+
+/// Measures a single request.
+/// The stream stats are recorded once the meter is finished or dropped,
+/// so the failed and partially read responses are recorded too.
+pub(crate) struct Meter {
+    sink: Option<Arc<dyn MetricsSink>>,
+    endpoint: &'static str,
+    start: Instant,
+    stats: StreamStats,
+    done: bool,
+}
+
+impl Meter {
+    /// Records the latency once the response headers with the given status have been received.
+    /// Nothing else is recorded for the unsuccessful responses.
+    pub(crate) fn response(&mut self, status: StatusCode) {
+        if let Some(sink) = &self.sink {
+            sink.record_latency(self.endpoint, self.start.elapsed());
+        }
+        if !status.is_success() {
+            self.sink = None;
+        }
+    }
+
+    /// Accounts for the received response chunk of the given size.
+    pub(crate) fn chunk(&mut self, size: usize) {
+        if let Some(sink) = &self.sink {
+            if self.stats.chunks == 0 {
+                sink.record_ttfb(self.endpoint, self.start.elapsed());
+            }
+            self.stats.bytes += size as u64;
+            self.stats.chunks += 1;
+        }
+    }
+
+    /// Records the stream stats.
+    pub(crate) fn finish(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        if let Some(sink) = &self.sink {
+            let stats = StreamStats {
+                duration: self.start.elapsed(),
+                ..self.stats.clone()
+            };
+            sink.record_stream(self.endpoint, stats);
+        }
+    }
+}
+
+impl Drop for Meter {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Response stream which records the stream metrics.
+/// The stream stats are recorded once the stream ends or is dropped.
+pub(crate) struct MeteredStream<S> {
+    stream: Pin<Box<S>>,
+    meter: Meter,
+}
+
+impl<S> MeteredStream<S> {
+    pub(crate) fn new(stream: S, meter: Meter) -> Self {
+        MeteredStream {
+            stream: Box::pin(stream),
+            meter,
+        }
+    }
+}
+
+impl<S> Stream for MeteredStream<S>
+where
+    S: Stream<Item = StreamResult<Bytes>>,
+{
+    type Item = StreamResult<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.stream.as_mut().poll_next(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => self.meter.chunk(chunk.len()),
+            Poll::Ready(None) => self.meter.finish(),
+            _ => {}
+        }
+
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio_stream::StreamExt;
+
+    #[derive(Debug, PartialEq)]
+    enum Record {
+        Latency(&'static str),
+        Ttfb(&'static str),
+        Stream(&'static str, u64, u64),
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingSink(Arc<Mutex<Vec<Record>>>);
+
+    impl MetricsSink for RecordingSink {
+        fn record_latency(&self, endpoint: &'static str, _latency: Duration) {
+            self.0.lock().unwrap().push(Record::Latency(endpoint));
+        }
+
+        fn record_ttfb(&self, endpoint: &'static str, _ttfb: Duration) {
+            self.0.lock().unwrap().push(Record::Ttfb(endpoint));
+        }
+
+        fn record_stream(&self, endpoint: &'static str, stats: StreamStats) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(Record::Stream(endpoint, stats.bytes, stats.chunks));
+        }
+    }
+
+    impl RecordingSink {
+        fn records(&self) -> Vec<Record> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    fn metrics() -> (Metrics, RecordingSink) {
+        let sink = RecordingSink::default();
+        (Metrics::new(Arc::new(sink.clone())), sink)
+    }
+
+    #[test]
+    fn meter_records_once() {
+        let (metrics, sink) = metrics();
+        let mut meter = metrics.meter("stream_audio");
+        meter.response(StatusCode::OK);
+        meter.chunk(3);
+        meter.chunk(4);
+        meter.finish();
+        meter.finish();
+        drop(meter);
+        assert_eq!(
+            sink.records(),
+            vec![
+                Record::Latency("stream_audio"),
+                Record::Ttfb("stream_audio"),
+                Record::Stream("stream_audio", 7, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn meter_records_on_drop() {
+        let (metrics, sink) = metrics();
+        let mut meter = metrics.meter("stream_audio");
+        meter.response(StatusCode::OK);
+        meter.chunk(3);
+        drop(meter);
+        assert_eq!(
+            sink.records(),
+            vec![
+                Record::Latency("stream_audio"),
+                Record::Ttfb("stream_audio"),
+                Record::Stream("stream_audio", 3, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn meter_skips_failed_responses() {
+        let (metrics, sink) = metrics();
+        let mut meter = metrics.meter("stream_audio");
+        meter.response(StatusCode::BAD_REQUEST);
+        meter.chunk(3);
+        meter.finish();
+        assert_eq!(sink.records(), vec![Record::Latency("stream_audio")]);
+    }
+
+    #[test]
+    fn latency_meter_records_latency_only() {
+        let (metrics, sink) = metrics();
+        let mut meter = metrics.latency_meter("get_tts_job");
+        meter.response(StatusCode::OK);
+        drop(meter);
+        assert_eq!(sink.records(), vec![Record::Latency("get_tts_job")]);
+    }
+
+    #[tokio::test]
+    async fn metered_stream_records_once() {
+        let (metrics, sink) = metrics();
+        let mut meter = metrics.meter("stream_audio");
+        meter.response(StatusCode::OK);
+        let chunks = vec![Ok(Bytes::from("ab")), Ok(Bytes::from("cde"))];
+        let mut stream = MeteredStream::new(tokio_stream::iter(chunks), meter);
+        while stream.next().await.is_some() {}
+        assert!(stream.next().await.is_none());
+        drop(stream);
+        assert_eq!(
+            sink.records(),
+            vec![
+                Record::Latency("stream_audio"),
+                Record::Ttfb("stream_audio"),
+                Record::Stream("stream_audio", 5, 2),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn metered_stream_records_on_drop() {
+        let (metrics, sink) = metrics();
+        let meter = metrics.meter("stream_audio");
+        let chunks = vec![Ok(Bytes::from("ab")), Ok(Bytes::from("cde"))];
+        let mut stream = MeteredStream::new(tokio_stream::iter(chunks), meter);
+        assert!(stream.next().await.is_some());
+        drop(stream);
+        assert_eq!(
+            sink.records(),
+            vec![
+                Record::Ttfb("stream_audio"),
+                Record::Stream("stream_audio", 2, 1),
+            ]
+        );
+    }
+}
@@ -15,27 +15,47 @@
 //! for each separate API call.
 //!
 
+pub mod credentials;
 pub mod job;
+pub mod journal;
+pub mod metrics;
+pub mod profile;
 pub mod stream;
 pub mod tts;
 pub mod voice;
 
 use crate::{error::*, prelude::*};
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use credentials::CredentialProvider;
 use job::{TTSJob, TTSJobReq, TTS_JOB_PATH};
+use journal::{Journal, JournalEntry, JournaledStream};
+use metrics::{Meter, MeteredStream, Metrics, MetricsSink};
 use reqwest::{
     header::{
         HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_LOCATION, CONTENT_TYPE,
         USER_AGENT,
     },
-    multipart, Body, Method, Request, Response, Url,
+    multipart, Body, IntoUrl, Method, Request, RequestBuilder, Response, StatusCode, Url,
 };
-use std::env;
-use stream::{TTSStreamReq, TTSStreamURL, TTS_STREAM_PATH};
-use tokio_stream::Stream;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::Duration,
+};
+use stream::{
+    TTSInputStreamOpts, TTSPipelineOpts, TTSStreamReq, TTSStreamURL, TextSender, TTS_STREAM_PATH,
+};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tts::OutputFormat;
 use voice::{
     CloneVoiceFileRequest, CloneVoiceURLRequest, ClonedVoice, DeleteClonedVoiceRequest,
-    DeleteClonedVoiceResp, Voice, CLONED_VOICES_INSTANT_PATH, CLONED_VOICES_PATH, VOICES_PATH,
+    DeleteClonedVoiceResp, UploadOpts, Voice, CLONED_VOICES_INSTANT_PATH, CLONED_VOICES_PATH,
+    VOICES_PATH,
 };
 
 /// API Base URL.
@@ -53,13 +73,39 @@ const V1_PATH: &str = "/v1";
 pub const USER_ID_HEADER: &str = "X-USER-ID";
 /// API client `User-Agent`.
 pub const CLIENT_USER_AGENT: &str = "milosgajdos/playht_rs";
+/// Number of audio chunks buffered by the input streams.
+const INPUT_STREAM_BUFFER_SIZE: usize = 64;
 
 /// <https://play.ht> API client.
-#[derive(Debug)]
+///
+/// The client is cheap to clone: all the clones share the same HTTP connection pool,
+/// API credentials, journal and metrics sink, so there is no need to wrap it in [`Arc`].
+/// Create the client once when your application starts and clone it wherever you need it,
+/// such as into the shared state of your web server handlers:
+///
+/// ```ignore
+/// // axum
+/// let app = Router::new()
+///     .route("/tts", post(tts))
+///     .with_state(api::Client::new());
+///
+/// async fn tts(State(client): State<api::Client>, Json(req): Json<TTSStreamReq>) -> impl IntoResponse {
+///     // use the client
+/// }
+///
+/// // actix-web
+/// let client = api::Client::new();
+/// HttpServer::new(move || App::new().app_data(web::Data::new(client.clone())).service(tts));
+/// ```
+#[derive(Debug, Clone)]
 pub struct Client {
     client: reqwest::Client,
-    url: Url,
-    headers: HeaderMap,
+    url: Arc<Url>,
+    headers: Arc<RwLock<Arc<HeaderMap>>>,
+    credential_provider: Option<Arc<dyn CredentialProvider>>,
+    journal: Option<Arc<Journal>>,
+    metrics: Metrics,
+    write_high_watermark: Option<usize>,
 }
 
 /// Provides <https://play.ht> API client implementation.
@@ -71,9 +117,7 @@ impl Client {
         // NOTE: unwrap is warranted because default()
         // only sets up default configuration which
         // must contain valid client configuration.
-        let c = ClientBuilder::default().build().unwrap();
-
-        c
+        ClientBuilder::default().build().unwrap()
     }
 
     /// Returns the remote host address as a string.
@@ -92,11 +136,10 @@ impl Client {
     /// this might come in handy, such as when a new API endpoint
     /// is added and you don't want to wait for it to be added to this crate.
     pub fn build_request<T: Into<Body>>(&self, method: Method, body: T) -> Result<Request> {
-        let mut req_builder = self.client.request(method, self.url.clone());
-        for (name, value) in &self.headers {
-            req_builder = req_builder.header(name, value);
-        }
-        let req = req_builder.body(body).build()?;
+        let req = self
+            .request(method, (*self.url).clone())
+            .body(body)
+            .build()?;
 
         Ok(req)
     }
@@ -115,9 +158,7 @@ impl Client {
     pub async fn get_stock_voices(&self) -> Result<Vec<Voice>> {
         let voices_url = format!("{}{}", self.url.as_str(), VOICES_PATH);
         let resp = self
-            .client
-            .get(voices_url)
-            .headers(self.headers.clone())
+            .request(Method::GET, voices_url)
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .send()
             .await?;
@@ -131,14 +172,77 @@ impl Client {
         Err(Box::new(Error::APIError(api_error)))
     }
 
+    /// Writes the audio sample of the given stock voice into the given writer.
+    /// This lets you preview the voice before using it for TTS.
+    /// NOTE: the API credentials are only sent along if the sample is hosted by the API,
+    /// i.e. its URL has the same scheme, host and port as the API URL.
+    pub async fn download_voice_sample<W>(&self, w: &mut W, voice: &Voice) -> Result<()>
+    where
+        W: tokio::io::AsyncWriteExt + Unpin,
+    {
+        let mut meter = self.metrics.meter("download_voice_sample");
+        let resp = self
+            .voice_sample_request(voice)?
+            .send()
+            .await?
+            .error_for_status()?;
+        meter.response(resp.status());
+
+        Self::write_body(
+            w,
+            resp.bytes_stream(),
+            &mut meter,
+            self.write_high_watermark,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Streams the audio sample of the given stock voice.
+    /// Unlike [`Client::download_voice_sample`] this method returns an async stream object
+    /// that streams raw audio data of the voice sample.
+    pub async fn stream_voice_sample(
+        &self,
+        voice: &Voice,
+    ) -> Result<impl Stream<Item = StreamResult<Bytes>>> {
+        let mut meter = self.metrics.meter("stream_voice_sample");
+        let resp = self
+            .voice_sample_request(voice)?
+            .send()
+            .await?
+            .error_for_status()?;
+        meter.response(resp.status());
+
+        Ok(MeteredStream::new(resp.bytes_stream(), meter))
+    }
+
+    fn voice_sample_request(&self, voice: &Voice) -> Result<reqwest::RequestBuilder> {
+        let Some(sample) = voice.sample.as_deref() else {
+            return Err(Box::new(Error::VoiceSampleError(format!(
+                "voice {} has no sample",
+                voice.id
+            ))));
+        };
+        let sample_url = sample.parse::<Url>()?;
+
+        let req_builder = if sample_url.origin() == self.url.origin() {
+            self.request(Method::GET, sample_url)
+        } else {
+            self.client
+                .get(sample_url)
+                .header(USER_AGENT, CLIENT_USER_AGENT)
+        };
+
+        Ok(req_builder)
+    }
+
     /// Returns all cloned voices.
     /// See the [official docs](https://docs.play.ht/reference/api-list-cloned-voices);
     pub async fn get_cloned_voices(&self) -> Result<Vec<ClonedVoice>> {
         let voices_url = format!("{}{}", self.url.as_str(), CLONED_VOICES_PATH);
         let resp = self
-            .client
-            .get(voices_url)
-            .headers(self.headers.clone())
+            .request(Method::GET, voices_url)
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .send()
             .await?;
@@ -153,38 +257,74 @@ impl Client {
     }
 
     /// Clones a voice clone from a file specified in the [`request`][voice::CloneVoiceFileRequest].
+    /// The sample file is streamed from disk and the failed uploads are retried
+    /// as per the default [`upload options`][voice::UploadOpts].
     /// See the [official docs](https://docs.play.ht/reference/api-create-instant-voice-clone).
     pub async fn clone_voice_from_file(&self, req: &CloneVoiceFileRequest) -> Result<ClonedVoice> {
-        let voice_name_part = multipart::Part::text(req.voice_name.clone()).mime_str(TEXT_PLAIN)?;
-        let sample_file_part = multipart::Part::bytes(std::fs::read(&req.sample_file)?)
-            .file_name(req.sample_file.clone())
-            .mime_str(&req.mime_type)?;
-
-        let form = multipart::Form::new()
-            .part("voice_name", voice_name_part)
-            .part("sample_file", sample_file_part);
+        self.clone_voice_from_file_with_opts(req, &UploadOpts::default())
+            .await
+    }
 
+    /// Clones a voice clone from a file specified in the [`request`][voice::CloneVoiceFileRequest]
+    /// using the given [`upload options`][voice::UploadOpts].
+    /// Unlike [`Client::clone_voice_from_file`] this lets you track the upload progress
+    /// and tune the upload buffering and retries, which comes in handy for large sample files.
+    /// The uploads are retried on connection errors, on errors raised before the whole
+    /// sample file has been streamed, and when the API is rate limiting or unavailable.
+    /// See the [official docs](https://docs.play.ht/reference/api-create-instant-voice-clone).
+    pub async fn clone_voice_from_file_with_opts(
+        &self,
+        req: &CloneVoiceFileRequest,
+        opts: &UploadOpts,
+    ) -> Result<ClonedVoice> {
         let clone_voice_url = format!("{}{}", self.url.as_str(), CLONED_VOICES_INSTANT_PATH);
-        let resp = self
-            .client
-            .post(clone_voice_url)
-            .headers(self.headers.clone())
-            .header(ACCEPT, APPLICATION_JSON)
-            .header(
-                CONTENT_TYPE,
-                format!("{}; boundary={}", MULTIPART_FORM, form.boundary()),
-            )
-            .multipart(form)
-            .send()
-            .await?;
-
-        if resp.status().is_success() {
-            let voice: ClonedVoice = resp.json().await?;
-            return Ok(voice);
+        let mut backoff = opts.retry_backoff;
+        let mut retries = 0;
+
+        loop {
+            let voice_name_part =
+                multipart::Part::text(req.voice_name.clone()).mime_str(TEXT_PLAIN)?;
+            let streamed = Arc::new(AtomicBool::new(false));
+            let sample_file_part = req.sample_file_part(opts, streamed.clone()).await?;
+
+            let form = multipart::Form::new()
+                .part("voice_name", voice_name_part)
+                .part("sample_file", sample_file_part);
+
+            let resp = self
+                .request(Method::POST, &clone_voice_url)
+                .header(ACCEPT, APPLICATION_JSON)
+                .header(
+                    CONTENT_TYPE,
+                    format!("{}; boundary={}", MULTIPART_FORM, form.boundary()),
+                )
+                .multipart(form)
+                .send()
+                .await;
+
+            let can_retry = retries < opts.max_retries;
+            match resp {
+                Ok(resp) if resp.status().is_success() => {
+                    let voice: ClonedVoice = resp.json().await?;
+                    return Ok(voice);
+                }
+                // NOTE: cloning a voice is not idempotent, so only the requests
+                // which were rejected before being processed are retried.
+                Ok(resp) if can_retry && voice::can_retry_upload_status(resp.status()) => {}
+                Ok(resp) => {
+                    let api_error: APIError = resp.json().await?;
+                    return Err(Box::new(Error::APIError(api_error)));
+                }
+                Err(err)
+                    if can_retry
+                        && voice::can_retry_upload(&err, streamed.load(Ordering::Acquire)) => {}
+                Err(err) => return Err(Box::new(err)),
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            retries += 1;
         }
-
-        let api_error: APIError = resp.json().await?;
-        Err(Box::new(Error::APIError(api_error)))
     }
 
     /// Creates a voice clone from the URL specified in the [`request`][voice::CloneVoiceURLRequest].
@@ -193,9 +333,7 @@ impl Client {
         let body = serde_json::to_string(req)?;
         let clone_voice_url = format!("{}{}", self.url.as_str(), CLONED_VOICES_PATH);
         let resp = self
-            .client
-            .post(clone_voice_url)
-            .headers(self.headers.clone())
+            .request(Method::POST, clone_voice_url)
             .header(ACCEPT, APPLICATION_JSON)
             .body(body)
             .send()
@@ -219,10 +357,8 @@ impl Client {
         let body = serde_json::to_string(req)?;
         let clone_voice_url = format!("{}{}", self.url.as_str(), CLONED_VOICES_PATH);
         let resp = self
-            .client
-            .delete(clone_voice_url)
+            .request(Method::DELETE, clone_voice_url)
             .body(body)
-            .headers(self.headers.clone())
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .header(ACCEPT, APPLICATION_JSON)
             .send()
@@ -240,17 +376,29 @@ impl Client {
     /// Creates an async TTS job and returns it.
     /// See the [official docs](https://docs.play.ht/reference/api-generate-audio).
     pub async fn create_tts_job(&self, req: &TTSJobReq) -> Result<TTSJob> {
+        req.validate().map_err(Error::ValidationError)?;
+        let entry = JournalEntry::from(req);
+        let res = self.send_tts_job(req).await;
+        match &res {
+            Ok(tts_job) => self.record(entry.submitted(Some(tts_job.id.clone()))),
+            Err(err) => self.record(entry.failed(err)),
+        }
+
+        res
+    }
+
+    async fn send_tts_job(&self, req: &TTSJobReq) -> Result<TTSJob> {
         let body = serde_json::to_string(req)?;
         let tts_job_url = format!("{}{}", self.url.as_str(), TTS_JOB_PATH);
+        let mut meter = self.metrics.latency_meter("create_tts_job");
         let resp = self
-            .client
-            .post(tts_job_url)
+            .request(Method::POST, tts_job_url)
             .body(body)
-            .headers(self.headers.clone())
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .header(ACCEPT, APPLICATION_JSON)
             .send()
             .await?;
+        meter.response(resp.status());
 
         if resp.status().is_success() {
             let tts_job: TTSJob = resp.json().await?;
@@ -273,27 +421,37 @@ impl Client {
     where
         W: tokio::io::AsyncWriteExt + Unpin,
     {
+        req.validate().map_err(Error::ValidationError)?;
         let body = serde_json::to_string(req)?;
         let tts_job_url = format!("{}{}", self.url.as_str(), TTS_JOB_PATH);
-        let mut resp = self
-            .client
-            .post(tts_job_url)
+        let mut meter = self.metrics.meter("create_tts_job_write_progress_stream");
+        let resp = self
+            .request(Method::POST, tts_job_url)
             .body(body)
-            .headers(self.headers.clone())
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .header(ACCEPT, TEXT_EVENT_STREAM)
             .send()
             .await?;
+        meter.response(resp.status());
 
         let stream_url = resp
             .headers()
             .get(CONTENT_LOCATION)
             .and_then(|hv| hv.to_str().ok().map(|s| s.to_string()));
 
-        while let Some(chunk) = resp.chunk().await? {
-            w.write_all(&chunk).await?;
+        let entry = JournalEntry::from(req);
+        if resp.status().is_success() {
+            let job_id = stream_url
+                .as_deref()
+                .and_then(|url| url.rsplit('/').next())
+                .map(|id| id.to_string());
+            self.record(entry.submitted(job_id));
+        } else {
+            self.record(entry.failed(resp.status()));
         }
 
+        Self::write_body(w, resp.bytes_stream(), &mut meter, None).await?;
+
         Ok(stream_url)
     }
 
@@ -301,13 +459,13 @@ impl Client {
     /// See the [official docs](https://docs.play.ht/reference/api-get-tts-data).
     pub async fn get_tts_job(&self, id: String) -> Result<TTSJob> {
         let tts_job_url = format!("{}{}/{}", self.url.as_str(), TTS_JOB_PATH, id);
+        let mut meter = self.metrics.latency_meter("get_tts_job");
         let resp = self
-            .client
-            .get(tts_job_url)
-            .headers(self.headers.clone())
+            .request(Method::GET, tts_job_url)
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .send()
             .await?;
+        meter.response(resp.status());
 
         if resp.status().is_success() {
             let tts_job: TTSJob = resp.json().await?;
@@ -327,17 +485,15 @@ impl Client {
         W: tokio::io::AsyncWriteExt + Unpin,
     {
         let tts_job_url = format!("{}{}/{}", self.url.as_str(), TTS_JOB_PATH, id);
-        let mut resp = self
-            .client
-            .get(tts_job_url)
-            .headers(self.headers.clone())
+        let mut meter = self.metrics.meter("write_tts_job_progress_stream");
+        let resp = self
+            .request(Method::GET, tts_job_url)
             .header(ACCEPT, TEXT_EVENT_STREAM)
             .send()
             .await?;
+        meter.response(resp.status());
 
-        while let Some(chunk) = resp.chunk().await? {
-            w.write_all(&chunk).await?;
-        }
+        Self::write_body(w, resp.bytes_stream(), &mut meter, None).await?;
 
         Ok(())
     }
@@ -351,15 +507,15 @@ impl Client {
         id: String,
     ) -> Result<impl Stream<Item = StreamResult<Bytes>>> {
         let tts_job_url = format!("{}{}/{}", self.url.as_str(), TTS_JOB_PATH, id);
+        let mut meter = self.metrics.meter("stream_tts_job_progress");
         let resp = self
-            .client
-            .get(tts_job_url)
-            .headers(self.headers.clone())
+            .request(Method::GET, tts_job_url)
             .header(ACCEPT, TEXT_EVENT_STREAM)
             .send()
             .await?;
+        meter.response(resp.status());
 
-        Ok(resp.bytes_stream())
+        Ok(MeteredStream::new(resp.bytes_stream(), meter))
     }
 
     /// Write the audio stream of the TTS job with the given id into the given writer.
@@ -372,16 +528,17 @@ impl Client {
         W: tokio::io::AsyncWriteExt + Unpin,
     {
         let tts_job_url = format!("{}{}/{}", self.url.as_str(), TTS_JOB_PATH, id);
-        let mut resp = self
-            .client
-            .get(tts_job_url)
-            .headers(self.headers.clone())
-            .send()
-            .await?;
-
-        while let Some(chunk) = resp.chunk().await? {
-            w.write_all(&chunk).await?;
-        }
+        let mut meter = self.metrics.meter("write_tts_job_audio_stream");
+        let resp = self.request(Method::GET, tts_job_url).send().await?;
+        meter.response(resp.status());
+
+        Self::write_body(
+            w,
+            resp.bytes_stream(),
+            &mut meter,
+            self.write_high_watermark,
+        )
+        .await?;
 
         Ok(())
     }
@@ -391,44 +548,155 @@ impl Client {
     /// Instead it immediately starts writing raw audio data into the given writer.
     /// See the [official docs](https://docs.play.ht/reference/api-generate-tts-audio-stream).
     pub async fn write_audio_stream<W>(&self, w: &mut W, req: &TTSStreamReq) -> Result<()>
+    where
+        W: tokio::io::AsyncWriteExt + Unpin,
+    {
+        req.validate().map_err(Error::ValidationError)?;
+        let entry = JournalEntry::from(req);
+        match self.send_audio_stream(w, req).await {
+            Ok(status) if status.is_success() => self.record(entry),
+            Ok(status) => self.record(entry.failed(status)),
+            Err(err) => {
+                self.record(entry.failed(&err));
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send_audio_stream<W>(&self, w: &mut W, req: &TTSStreamReq) -> Result<StatusCode>
     where
         W: tokio::io::AsyncWriteExt + Unpin,
     {
         let body = serde_json::to_string(req)?;
         let tts_stream_url = format!("{}{}", self.url.as_str(), TTS_STREAM_PATH);
 
-        let mut resp = self
-            .client
-            .post(tts_stream_url)
+        let mut meter = self.metrics.meter("write_audio_stream");
+        let resp = self
+            .request(Method::POST, tts_stream_url)
             .body(body)
-            .headers(self.headers.clone())
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .send()
             .await?;
+        meter.response(resp.status());
+
+        let status = resp.status();
+        Self::write_body(
+            w,
+            resp.bytes_stream(),
+            &mut meter,
+            self.write_high_watermark,
+        )
+        .await?;
+
+        Ok(status)
+    }
 
-        while let Some(chunk) = resp.chunk().await? {
-            w.write_all(&chunk).await?;
+    /// Writes TTS audio stream into the file at the given path and returns the path of the written file.
+    /// The audio is requested in the [`OutputFormat`][f] of the request; the extension of the format
+    /// is appended to the path unless it already has it, and the path must not have the extension
+    /// of another format. Extensions which don't belong to any format are kept as part of the file name,
+    /// so `out/take.v2` is written into `out/take.v2.mp3`.
+    /// Missing parent directories are created and the file is synced to disk once the audio has been
+    /// written; on Unix its directory is synced after the file has been moved into it as well.
+    /// NOTE: the file is only created once the API has accepted the request. The audio is written
+    /// into a temporary file next to it which replaces the file once all the audio has been written,
+    /// so the file is never left truncated if the streaming fails.
+    ///
+    /// [f]: tts::OutputFormat
+    pub async fn write_audio_to_file<P: AsRef<Path>>(
+        &self,
+        req: &TTSStreamReq,
+        path: P,
+    ) -> Result<PathBuf> {
+        req.validate().map_err(Error::ValidationError)?;
+        let format = req.output_format.clone().unwrap_or_default();
+        let path = audio_file_path(path.as_ref(), &format)?;
+
+        let entry = JournalEntry::from(req);
+        match self.send_audio_to_file(&path, req, &format).await {
+            Ok(()) => self.record(entry),
+            Err(err) => {
+                self.record(entry.failed(&err));
+                return Err(err);
+            }
         }
 
-        Ok(())
+        Ok(path)
+    }
+
+    async fn send_audio_to_file(
+        &self,
+        path: &Path,
+        req: &TTSStreamReq,
+        format: &OutputFormat,
+    ) -> Result<()> {
+        let body = serde_json::to_string(req)?;
+        let tts_stream_url = format!("{}{}", self.url.as_str(), TTS_STREAM_PATH);
+
+        let mut meter = self.metrics.meter("write_audio_to_file");
+        let resp = self
+            .request(Method::POST, tts_stream_url)
+            .body(body)
+            .header(CONTENT_TYPE, APPLICATION_JSON)
+            .header(ACCEPT, format.mime_type())
+            .send()
+            .await?;
+        meter.response(resp.status());
+
+        if !resp.status().is_success() {
+            let api_error: APIError = resp.json().await?;
+            return Err(Box::new(Error::APIError(api_error)));
+        }
+
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let tmp_path = partial_file_path(path);
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        let written = async {
+            Self::write_body(
+                &mut file,
+                resp.bytes_stream(),
+                &mut meter,
+                self.write_high_watermark,
+            )
+            .await?;
+            file.flush().await?;
+            file.sync_all().await?;
+            drop(file);
+            tokio::fs::rename(&tmp_path, path).await?;
+            #[cfg(unix)]
+            sync_parent_dir(path).await?;
+
+            Ok(())
+        }
+        .await;
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+        }
+
+        written
     }
 
     /// Fetches audio stream URL instead of streaming raw audio like [`Client::stream_audio`].
     /// You can use the returned URL for streaming the raw audio.
     /// See the [official docs](https://docs.play.ht/reference/api-generate-tts-audio-stream).
     pub async fn get_audio_stream_url(&self, req: &TTSStreamReq) -> Result<TTSStreamURL> {
+        req.validate().map_err(Error::ValidationError)?;
         let body = serde_json::to_string(req)?;
         let tts_stream_url = format!("{}{}", self.url.as_str(), TTS_STREAM_PATH);
 
+        let mut meter = self.metrics.latency_meter("get_audio_stream_url");
         let resp = self
-            .client
-            .post(tts_stream_url)
+            .request(Method::POST, tts_stream_url)
             .body(body)
-            .headers(self.headers.clone())
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .header(ACCEPT, APPLICATION_JSON)
             .send()
             .await?;
+        meter.response(resp.status());
 
         if resp.status().is_success() {
             let audio_stream_url: TTSStreamURL = resp.json().await?;
@@ -447,28 +715,297 @@ impl Client {
         &self,
         req: &TTSStreamReq,
     ) -> Result<impl Stream<Item = StreamResult<Bytes>>> {
+        req.validate().map_err(Error::ValidationError)?;
         let body = serde_json::to_string(req)?;
         let tts_stream_url = format!("{}{}", self.url.as_str(), TTS_STREAM_PATH);
 
+        let mut meter = self.metrics.meter("stream_audio");
         let resp = self
-            .client
-            .post(tts_stream_url)
+            .request(Method::POST, tts_stream_url)
             .body(body)
-            .headers(self.headers.clone())
             .header(CONTENT_TYPE, APPLICATION_JSON)
             .send()
-            .await?;
+            .await;
+
+        let entry = JournalEntry::from(req);
+        let resp = match resp {
+            Ok(resp) => resp,
+            Err(err) => {
+                self.record(entry.failed(&err));
+                return Err(Box::new(err));
+            }
+        };
+        meter.response(resp.status());
+        let entry = if resp.status().is_success() {
+            entry
+        } else {
+            entry.failed(resp.status())
+        };
+
+        Ok(JournaledStream::new(
+            MeteredStream::new(resp.bytes_stream(), meter),
+            self.journal.clone(),
+            entry,
+        ))
+    }
+
+    /// Streams raw TTS audio of the text fed incrementally via the returned [`TextSender`].
+    /// Unlike [`Client::stream_audio`] the text does not need to be known up front,
+    /// which is handy when the text is generated token by token, such as by an LLM.
+    /// The buffered text is sent for synthesis as dictated by the [`flush mode`][stream::FlushMode]
+    /// and the audio of each flushed text fragment is streamed in the order it was received.
+    /// The text in the given request is ignored; all the other request options are applied
+    /// to every flushed text fragment. The returned stream ends once the input has been closed.
+    /// The request options are validated up front. The flushed fragments which exceed the text limit
+    /// of the voice engine are split at the sentence boundaries or whitespace before they're synthesized.
+    /// Dropping the returned stream closes the input; [`TextSender::send`] fails afterwards.
+    /// NOTE: play.ht does not provide a duplex text input transport, so every flushed fragment
+    /// is synthesized by a separate HTTP stream request. Up to [`TTSInputStreamOpts::max_concurrency`]
+    /// requests are in flight at once, so the fragments are synthesized while the audio of
+    /// the previous ones is still being streamed, just like with [`Client::pipelined_audio`].
+    /// NOTE: this method must be called from within the [`tokio`] runtime.
+    /// See the [official docs](https://docs.play.ht/reference/api-generate-tts-audio-stream).
+    pub fn stream_audio_input(
+        &self,
+        req: &TTSStreamReq,
+        opts: &TTSInputStreamOpts,
+    ) -> Result<(TextSender, impl Stream<Item = StreamResult<Bytes>>)> {
+        let opts_req = TTSStreamReq {
+            text: None,
+            ..req.clone()
+        };
+        if let Err(mut err) = opts_req.validate() {
+            err.violations
+                .retain(|v| !matches!(v, Violation::MissingText));
+            if !err.violations.is_empty() {
+                return Err(Box::new(Error::ValidationError(err)));
+            }
+        }
+
+        let (text_tx, text_rx) = mpsc::unbounded_channel();
+        let (audio_tx, audio_rx) = mpsc::channel(INPUT_STREAM_BUFFER_SIZE);
+
+        tokio::spawn(stream::stream_input(
+            self.clone(),
+            req.clone(),
+            opts.clone(),
+            text_rx,
+            audio_tx,
+        ));
+
+        Ok((TextSender::new(text_tx), ReceiverStream::new(audio_rx)))
+    }
+
+    /// Streams raw TTS audio of the text of the given request sentence by sentence.
+    /// The text is split into sentences which are synthesized by up to
+    /// [`TTSPipelineOpts::max_concurrency`] overlapping stream requests. The audio is
+    /// streamed in the order of the sentences, so the playback can start as soon as the
+    /// first sentence has been synthesized instead of after the whole text.
+    /// Each sentence is validated and journaled as a separate synthesis.
+    pub fn pipelined_audio(
+        &self,
+        req: &TTSStreamReq,
+        opts: &TTSPipelineOpts,
+    ) -> Result<impl Stream<Item = StreamResult<Bytes>>> {
+        let reqs: Vec<TTSStreamReq> = stream::sentences(req.text.as_deref().unwrap_or_default())
+            .into_iter()
+            .map(|sentence| TTSStreamReq {
+                text: Some(sentence.to_string()),
+                ..req.clone()
+            })
+            .collect();
+        if reqs.is_empty() {
+            let violations = vec![Violation::MissingText];
+            return Err(Box::new(Error::ValidationError(ValidationError {
+                violations,
+            })));
+        }
+        for req in &reqs {
+            req.validate().map_err(Error::ValidationError)?;
+        }
+
+        let (audio_tx, audio_rx) = mpsc::channel(INPUT_STREAM_BUFFER_SIZE);
+        tokio::spawn(stream::pipeline(
+            self.clone(),
+            tokio_stream::iter(reqs),
+            opts.max_concurrency,
+            audio_tx,
+            "pipelined_audio",
+        ));
+
+        Ok(ReceiverStream::new(audio_rx))
+    }
+
+    /// Reloads the API credentials from the credential provider the client has been built with.
+    /// The refreshed credentials are shared by all the clones of the client.
+    /// Call this once the credentials have been rotated in the secret store,
+    /// or periodically if the store issues short-lived credentials.
+    /// See [`ClientBuilder::credential_provider`].
+    pub async fn refresh_credentials(&self) -> Result<()> {
+        let Some(provider) = &self.credential_provider else {
+            return Err(Box::new(Error::CredentialsError(
+                "client has no credential provider".to_string(),
+            )));
+        };
+        let creds = provider.credentials().await?;
+
+        let mut headers = HeaderMap::clone(&self.headers());
+        credentials::set_headers(&mut headers, &creds)?;
+        *self.headers.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(headers);
+
+        Ok(())
+    }
+
+    /// Returns the headers sent along with every API request.
+    fn headers(&self) -> Arc<HeaderMap> {
+        self.headers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Returns a new request builder with the API headers set.
+    /// NOTE: the API headers are copied into every request.
+    fn request<U: IntoUrl>(&self, method: Method, url: U) -> RequestBuilder {
+        let headers = self.headers();
+        headers.iter().fold(
+            self.client.request(method, url),
+            |req_builder, (name, value)| req_builder.header(name.clone(), value.clone()),
+        )
+    }
+
+    /// Writes the response body stream into the given writer.
+    /// If the high watermark is set, the received chunks are buffered
+    /// and written once the buffered data reaches the high watermark.
+    /// The chunks are written straight into the writer otherwise.
+    async fn write_body<W, S>(
+        w: &mut W,
+        body: S,
+        meter: &mut Meter,
+        high_watermark: Option<usize>,
+    ) -> Result<()>
+    where
+        W: tokio::io::AsyncWriteExt + Unpin,
+        S: Stream<Item = StreamResult<Bytes>>,
+    {
+        let mut stream = std::pin::pin!(body);
+        let mut buf = BytesMut::with_capacity(high_watermark.unwrap_or(0));
+
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    // NOTE: the audio buffered before the error is still written,
+                    // so the writer receives the same audio as without the buffering.
+                    if !buf.is_empty() {
+                        w.write_all(&buf).await?;
+                    }
+                    return Err(Box::new(err));
+                }
+            };
+            meter.chunk(chunk.len());
+            match high_watermark {
+                // NOTE: chunks which alone reach the high watermark skip the buffer.
+                Some(hwm) if buf.is_empty() && chunk.len() >= hwm => w.write_all(&chunk).await?,
+                Some(hwm) => {
+                    buf.extend_from_slice(&chunk);
+                    if buf.len() >= hwm {
+                        w.write_all(&buf).await?;
+                        buf.clear();
+                    }
+                }
+                None => w.write_all(&chunk).await?,
+            }
+        }
+        if !buf.is_empty() {
+            w.write_all(&buf).await?;
+        }
+        meter.finish();
+
+        Ok(())
+    }
+
+    /// Returns the synthesis journal if the client has been built with one.
+    /// See [`ClientBuilder::journal`].
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_deref()
+    }
+
+    fn record(&self, entry: JournalEntry) {
+        journal::record(self.journal.as_ref(), entry);
+    }
+}
+
+/// Returns the path of the audio file in the given format.
+/// The extension of the format is appended to the path unless it already has it;
+/// extensions which don't belong to any output format, such as `take.v2`, are kept
+/// as part of the file name, while the extensions of other output formats are rejected.
+fn audio_file_path(path: &Path, format: &OutputFormat) -> Result<PathBuf> {
+    let Some(ext) = path.extension().filter(|e| !e.is_empty()) else {
+        return Ok(path.with_extension(format.extension()));
+    };
+    let ext = ext.to_string_lossy();
+    match OutputFormat::from_extension(&ext) {
+        Some(f) if f == *format => Ok(path.to_path_buf()),
+        Some(_) => Err(Box::new(Error::OutputFileError(format!(
+            "extension {} of {} does not match the {} output format",
+            ext,
+            path.display(),
+            format.extension()
+        )))),
+        None => {
+            let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+            file_name.push(".");
+            file_name.push(format.extension());
+
+            Ok(path.with_file_name(file_name))
+        }
+    }
+}
+
+/// Returns the path of the temporary file the audio is written into before it's moved to the given path.
+fn partial_file_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".part");
+
+    path.with_file_name(file_name)
+}
+
+/// Syncs the directory containing the given path to disk, which makes the renames into it durable.
+#[cfg(unix)]
+async fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    let dir = path
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
 
-        Ok(resp.bytes_stream())
+    tokio::fs::File::open(dir).await?.sync_all().await
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 /// Configures and builds the [`Client`].
+///
+/// The underlying HTTP connection pool uses the [`reqwest`] defaults which already
+/// suit the sustained streaming workloads: all the idle connections are kept in the pool
+/// for 90 seconds and probed by TCP keepalive once idle for 15 seconds, so the consecutive
+/// TTS streams reuse the pooled connections. You can tune the pool via the builder methods.
+/// NOTE: the pool settings are ignored if you provide your own [`reqwest::Client`]
+/// via [`ClientBuilder::req_client`].
 #[derive(Debug)]
 pub struct ClientBuilder {
     client: Option<reqwest::Client>,
+    http: reqwest::ClientBuilder,
     url: Option<Url>,
     headers: Option<HeaderMap>,
+    journal: Option<Arc<Journal>>,
+    metrics: Metrics,
+    credential_provider: Option<Arc<dyn CredentialProvider>>,
+    write_high_watermark: Option<usize>,
 }
 
 impl ClientBuilder {
@@ -478,9 +1015,60 @@ impl ClientBuilder {
         Ok(cb)
     }
 
+    /// Creates a new builder with the API credentials loaded from the profile with the given name.
+    /// NOTE: the profile is never selected by the `PLAYHT_PROFILE` env var, and its credentials
+    /// take precedence over the `PLAYHT_SECRET_KEY` and `PLAYHT_USER_ID` env vars, so that
+    /// the client never uses the credentials of an account other than the one asked for.
+    /// See the [`profile`] module for more details.
+    pub fn from_profile(name: &str) -> Result<Self> {
+        let profile = profile::load_profile(name)?;
+        let (Some(secret_key), Some(user_id)) = (profile.secret_key, profile.user_id) else {
+            return Err(Box::new(Error::ProfileError(format!(
+                "profile {} is missing credentials",
+                name
+            ))));
+        };
+
+        let mut cb = ClientBuilder::default();
+        credentials::set_headers(
+            cb.headers.as_mut().unwrap(),
+            &credentials::Credentials {
+                secret_key,
+                user_id,
+            },
+        )?;
+
+        Ok(cb)
+    }
+
+    /// Creates a new builder with the API credentials read from the OS keychain.
+    /// See [`credentials::KeyringProvider`] for more details.
+    #[cfg(feature = "keyring")]
+    pub async fn from_keyring() -> Result<Self> {
+        ClientBuilder::default()
+            .credential_provider(credentials::KeyringProvider::default())
+            .await
+    }
+
+    /// Sets the provider of the API credentials.
+    /// The credentials are resolved right away and override any credentials
+    /// read from the env vars or the profile. The client keeps the provider
+    /// so that the credentials can be reloaded via [`Client::refresh_credentials`].
+    pub async fn credential_provider<P: CredentialProvider + 'static>(
+        mut self,
+        provider: P,
+    ) -> Result<Self> {
+        let creds = provider.credentials().await?;
+        credentials::set_headers(self.headers.as_mut().unwrap(), &creds)?;
+        self.credential_provider = Some(Arc::new(provider));
+
+        Ok(self)
+    }
+
     pub fn header(mut self, name: &str, value: &str) -> Result<Self> {
         let header_name = name.parse::<HeaderName>()?;
-        let header_value = value.parse::<HeaderValue>()?;
+        let mut header_value = value.parse::<HeaderValue>()?;
+        header_value.set_sensitive(header_name == AUTHORIZATION);
         self.headers
             .as_mut()
             .unwrap()
@@ -502,6 +1090,75 @@ impl ClientBuilder {
         Ok(self)
     }
 
+    /// Sets the time an idle pooled connection is kept alive for; 90 seconds by default.
+    /// Pass `None` to keep the idle connections alive indefinitely.
+    pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Result<Self> {
+        self.http = self.http.pool_idle_timeout(timeout);
+
+        Ok(self)
+    }
+
+    /// Sets the max number of idle pooled connections per host; unbounded by default.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Result<Self> {
+        self.http = self.http.pool_max_idle_per_host(max);
+
+        Ok(self)
+    }
+
+    /// Makes the client speak HTTP/2 straight away without negotiating it first.
+    /// This multiplexes all the requests over a single connection per host.
+    pub fn http2_prior_knowledge(mut self) -> Result<Self> {
+        self.http = self.http.http2_prior_knowledge();
+
+        Ok(self)
+    }
+
+    /// Sets how long the pooled connections stay idle before they're probed
+    /// by TCP keepalive; 15 seconds by default. Pass `None` to disable the TCP keepalive.
+    pub fn tcp_keepalive(mut self, time: Option<Duration>) -> Result<Self> {
+        self.http = self.http.tcp_keepalive(time);
+        // NOTE: the keepalive stays enabled with the system default time
+        // unless the probe interval and retries are unset too.
+        if time.is_none() {
+            self.http = self
+                .http
+                .tcp_keepalive_interval(None::<Duration>)
+                .tcp_keepalive_retries(None::<u32>);
+        }
+
+        Ok(self)
+    }
+
+    /// Sets the high watermark of the audio written into the writers by
+    /// the `write_*` and `download_*` client methods, such as [`Client::write_audio_stream`].
+    /// The received audio chunks are buffered until they reach the high watermark
+    /// and then written into the writer at once, which cuts down the number of writes
+    /// into unbuffered writers, such as files or sockets, when streaming long audio.
+    /// The audio is written chunk by chunk as it's being received by default.
+    /// NOTE: the SSE progress streams are never buffered.
+    pub fn write_high_watermark(mut self, bytes: Option<usize>) -> Result<Self> {
+        self.write_high_watermark = bytes;
+
+        Ok(self)
+    }
+
+    /// Enables the synthesis journal which appends a JSON line
+    /// per completed synthesis into the given writer.
+    /// See the [`journal`] module for more details.
+    pub fn journal<W: std::io::Write + Send + 'static>(mut self, w: W) -> Result<Self> {
+        self.journal = Some(Arc::new(Journal::new(w)));
+
+        Ok(self)
+    }
+
+    /// Sets the sink which records the client metrics.
+    /// See the [`metrics`] module for more details.
+    pub fn metrics<M: MetricsSink + 'static>(mut self, sink: M) -> Result<Self> {
+        self.metrics = Metrics::new(Arc::new(sink));
+
+        Ok(self)
+    }
+
     pub fn build(self) -> Result<Client> {
         let Some(url) = self.url else {
             return Err(Box::new(Error::ClientBuildError(
@@ -509,36 +1166,546 @@ impl ClientBuilder {
             )));
         };
 
+        let client = match self.client {
+            Some(client) => client,
+            None => self
+                .http
+                .build()
+                .map_err(|e| Error::ClientBuildError(e.to_string()))?,
+        };
+
         Ok(Client {
-            url,
-            client: self.client.unwrap(),
-            headers: self.headers.unwrap(),
+            url: Arc::new(url),
+            client,
+            headers: Arc::new(RwLock::new(Arc::new(self.headers.unwrap()))),
+            credential_provider: self.credential_provider,
+            write_high_watermark: self.write_high_watermark,
+            journal: self.journal,
+            metrics: self.metrics,
         })
     }
 }
 
 impl Default for ClientBuilder {
+    /// Creates a new builder with the API credentials read from the
+    /// `PLAYHT_SECRET_KEY` and `PLAYHT_USER_ID` env vars. If neither of them
+    /// is set, the credentials are read from the profile selected by
+    /// the `PLAYHT_PROFILE` env var or from the default profile.
+    /// The invalid credentials are skipped.
+    /// See the [`profile`] module for more details.
     fn default() -> Self {
+        // NOTE: the credentials are never mixed from the env vars and the profile,
+        // as that could pair the secret key of one account with the user ID of another.
+        let (secret_key, user_id) = match (
+            env::var("PLAYHT_SECRET_KEY").ok(),
+            env::var("PLAYHT_USER_ID").ok(),
+        ) {
+            (None, None) => profile::default_profile()
+                .map(|p| (p.secret_key, p.user_id))
+                .unwrap_or_default(),
+            creds => creds,
+        };
+
         let mut headers = HeaderMap::new();
-        if let Ok(secret_key) = env::var("PLAYHT_SECRET_KEY") {
-            headers.append(
-                AUTHORIZATION.as_str(),
-                HeaderValue::from_str(&secret_key).unwrap(),
-            );
+        if let Some(secret_key) = secret_key {
+            let _ = credentials::set_secret_key(&mut headers, &secret_key);
         }
-        if let Ok(user_id) = env::var("PLAYHT_USER_ID") {
-            headers.append(USER_ID_HEADER, HeaderValue::from_str(&user_id).unwrap());
+        if let Some(user_id) = user_id {
+            let _ = credentials::set_user_id(&mut headers, &user_id);
         }
         headers.append(USER_AGENT, HeaderValue::from_static(CLIENT_USER_AGENT));
 
         let url = format!("{}{}", BASE_URL, V2_PATH).parse::<Url>().ok();
 
-        let client = reqwest::Client::new();
-
         Self {
             url,
-            client: Some(client),
+            client: None,
+            http: reqwest::Client::builder(),
             headers: Some(headers),
+            journal: None,
+            metrics: Metrics::default(),
+            credential_provider: None,
+            write_high_watermark: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::SocketAddr, sync::Mutex};
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    /// Fails to compile if the client can't be cloned into and shared across tasks.
+    fn _assert<T: Clone + Send + Sync>() {}
+
+    #[test]
+    fn client_is_clone_send_sync() {
+        _assert::<Client>();
+    }
+
+    /// Records every write it receives.
+    #[derive(Default)]
+    struct RecordingWriter(Vec<Vec<u8>>);
+
+    impl tokio::io::AsyncWrite for RecordingWriter {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            self.0.push(buf.to_vec());
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Writes the given chunks via [`Client::write_body`] and returns the recorded writes.
+    async fn write_chunks(
+        chunks: Vec<StreamResult<Bytes>>,
+        high_watermark: Option<usize>,
+    ) -> (Vec<Vec<u8>>, Result<()>) {
+        let mut w = RecordingWriter::default();
+        let mut meter = Metrics::default().meter("test");
+        let res = Client::write_body(
+            &mut w,
+            tokio_stream::iter(chunks),
+            &mut meter,
+            high_watermark,
+        )
+        .await;
+
+        (w.0, res)
+    }
+
+    fn chunk(data: &'static [u8]) -> StreamResult<Bytes> {
+        Ok(Bytes::from_static(data))
+    }
+
+    #[tokio::test]
+    async fn write_body_without_high_watermark() {
+        let (writes, res) = write_chunks(vec![chunk(b"ab"), chunk(b"c")], None).await;
+        assert!(res.is_ok());
+        assert_eq!(writes, vec![b"ab".to_vec(), b"c".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn write_body_buffers_until_high_watermark() {
+        let chunks = vec![chunk(b"ab"), chunk(b"cd"), chunk(b"e"), chunk(b"f")];
+        let (writes, res) = write_chunks(chunks, Some(4)).await;
+        assert!(res.is_ok());
+        assert_eq!(writes, vec![b"abcd".to_vec(), b"ef".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn write_body_oversize_chunks_skip_buffer() {
+        let chunks = vec![
+            chunk(b"abcdef"),
+            chunk(b"g"),
+            chunk(b"hijklm"),
+            chunk(b"nopq"),
+        ];
+        let (writes, res) = write_chunks(chunks, Some(4)).await;
+        assert!(res.is_ok());
+        assert_eq!(
+            writes,
+            vec![b"abcdef".to_vec(), b"ghijklm".to_vec(), b"nopq".to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn write_body_flushes_on_error() {
+        let err = reqwest::Client::new().get("http://").build().unwrap_err();
+        let chunks = vec![chunk(b"ab"), chunk(b"c"), Err(err), chunk(b"d")];
+        let (writes, res) = write_chunks(chunks, Some(4)).await;
+        assert!(res.is_err());
+        assert_eq!(writes, vec![b"abc".to_vec()]);
+    }
+
+    /// Serves empty responses to HTTP/1.1 requests without body.
+    /// Returns the server address and the client addresses of the accepted connections.
+    async fn serve_http1() -> (SocketAddr, Arc<Mutex<Vec<SocketAddr>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let conns = Arc::new(Mutex::new(Vec::new()));
+        let accepted = conns.clone();
+        tokio::spawn(async move {
+            while let Ok((mut conn, peer)) = listener.accept().await {
+                accepted.lock().unwrap().push(peer);
+                tokio::spawn(async move {
+                    let mut req = Vec::new();
+                    let mut buf = [0; 1024];
+                    while let Ok(n @ 1..) = conn.read(&mut buf).await {
+                        req.extend_from_slice(&buf[..n]);
+                        while let Some(end) = req.windows(4).position(|w| w == b"\r\n\r\n") {
+                            req.drain(..end + 4);
+                            let resp = b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n";
+                            if conn.write_all(resp).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        (addr, conns)
+    }
+
+    /// Sends two requests with the given pause in between and returns
+    /// the client addresses of the connections the server has accepted.
+    async fn get_twice(builder: ClientBuilder, pause: Duration) -> Vec<SocketAddr> {
+        let (addr, conns) = serve_http1().await;
+        let client = builder.build().unwrap().client;
+        let url = format!("http://{}/", addr);
+        for _ in 0..2 {
+            client
+                .get(&url)
+                .send()
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap();
+            tokio::time::sleep(pause).await;
+        }
+
+        let conns = conns.lock().unwrap().clone();
+        conns
+    }
+
+    /// Returns the first bytes the client built by the given builder sends to the server.
+    async fn first_bytes(builder: ClientBuilder, n: usize) -> Vec<u8> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let client = builder.build().unwrap().client;
+        tokio::spawn(async move { client.get(url).send().await });
+
+        let (mut conn, _) = listener.accept().await.unwrap();
+        let mut buf = vec![0; n];
+        conn.read_exact(&mut buf).await.unwrap();
+
+        buf
+    }
+
+    /// Returns the keepalive timer of the local TCP socket bound to the given address
+    /// in the clock ticks, or `None` if the keepalive is disabled.
+    #[cfg(target_os = "linux")]
+    fn keepalive_timer(addr: SocketAddr) -> Option<u64> {
+        let tcp = std::fs::read_to_string("/proc/net/tcp").unwrap();
+        let local = format!(":{:04X}", addr.port());
+        let socket = tcp
+            .lines()
+            .skip(1)
+            .map(|l| l.split_whitespace().collect::<Vec<_>>())
+            .find(|fields| fields[1].ends_with(&local))
+            .unwrap();
+        let (timer, when) = socket[5].split_once(':').unwrap();
+
+        (timer == "02").then(|| u64::from_str_radix(when, 16).unwrap())
+    }
+
+    /// Accepts the voice sample uploads and closes the connections without responding,
+    /// either right after reading the request headers or once the whole request has been read.
+    /// Returns the server address and the number of the accepted connections.
+    async fn serve_uploads(read_body: bool) -> (SocketAddr, Arc<Mutex<usize>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let conns = Arc::new(Mutex::new(0));
+        let accepted = conns.clone();
+        tokio::spawn(async move {
+            while let Ok((mut conn, _)) = listener.accept().await {
+                *accepted.lock().unwrap() += 1;
+                let mut req = Vec::new();
+                let mut buf = [0; 64 * 1024];
+                let end = loop {
+                    if let Some(end) = req.windows(4).position(|w| w == b"\r\n\r\n") {
+                        break end + 4;
+                    }
+                    let n = conn.read(&mut buf).await.unwrap();
+                    req.extend_from_slice(&buf[..n]);
+                };
+                if read_body {
+                    let headers = String::from_utf8_lossy(&req[..end]).to_lowercase();
+                    let length: usize = headers
+                        .lines()
+                        .find_map(|l| l.strip_prefix("content-length:"))
+                        .unwrap()
+                        .trim()
+                        .parse()
+                        .unwrap();
+                    let mut body = req.len() - end;
+                    while body < length {
+                        body += conn.read(&mut buf).await.unwrap();
+                    }
+                }
+            }
+        });
+
+        (addr, conns)
+    }
+
+    /// Uploads a sample file of the given size to the given server.
+    async fn upload_sample(addr: SocketAddr, size: usize, name: &str) -> Result<ClonedVoice> {
+        let sample_file =
+            std::env::temp_dir().join(format!("playht-{}-{}.wav", name, std::process::id()));
+        std::fs::write(&sample_file, vec![0; size]).unwrap();
+
+        let client = ClientBuilder {
+            url: Some(format!("http://{}", addr).parse().unwrap()),
+            ..ClientBuilder::default()
+        }
+        .build()
+        .unwrap();
+        let req = CloneVoiceFileRequest {
+            sample_file: sample_file.to_string_lossy().to_string(),
+            voice_name: "voice".to_string(),
+            mime_type: "audio/wav".to_string(),
+        };
+        let opts = UploadOpts {
+            max_retries: 2,
+            retry_backoff: Duration::from_millis(1),
+            ..UploadOpts::default()
+        };
+        let res = client.clone_voice_from_file_with_opts(&req, &opts).await;
+        std::fs::remove_file(&sample_file).unwrap();
+
+        res
+    }
+
+    #[tokio::test]
+    async fn clone_voice_retries_unsent_samples() {
+        let (addr, conns) = serve_uploads(false).await;
+        let res = upload_sample(addr, 16 * 1024 * 1024, "unsent").await;
+        assert!(res.is_err());
+        assert_eq!(*conns.lock().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn clone_voice_does_not_retry_sent_samples() {
+        let (addr, conns) = serve_uploads(true).await;
+        let res = upload_sample(addr, 1024, "sent").await;
+        assert!(res.is_err());
+        assert_eq!(*conns.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn from_profile_ignores_profile_env_var() {
+        let path = std::env::temp_dir().join(format!("playht-credentials-{}", std::process::id()));
+        let content = "
+            [custA]
+            secret_key = keyA
+            user_id = userA
+
+            [custB]
+            secret_key = keyB
+            user_id = userB
+        ";
+        std::fs::write(&path, content).unwrap();
+        env::set_var(profile::CREDENTIALS_FILE_ENV_VAR, &path);
+        env::set_var(profile::PROFILE_ENV_VAR, "custB");
+
+        let builder = ClientBuilder::from_profile("custA");
+
+        env::remove_var(profile::PROFILE_ENV_VAR);
+        env::remove_var(profile::CREDENTIALS_FILE_ENV_VAR);
+        std::fs::remove_file(&path).unwrap();
+
+        let headers = builder.unwrap().headers.unwrap();
+        assert_eq!(headers[AUTHORIZATION], "keyA");
+        assert_eq!(headers[USER_ID_HEADER], "userA");
+    }
+
+    #[tokio::test]
+    async fn pool_reuses_connections() {
+        let conns = get_twice(ClientBuilder::default(), Duration::from_millis(50)).await;
+        assert_eq!(conns.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn pool_max_idle_per_host() {
+        let builder = ClientBuilder::default().pool_max_idle_per_host(0).unwrap();
+        let conns = get_twice(builder, Duration::from_millis(50)).await;
+        assert_eq!(conns.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn pool_idle_timeout() {
+        let builder = ClientBuilder::default()
+            .pool_idle_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        let conns = get_twice(builder, Duration::from_millis(300)).await;
+        assert_eq!(conns.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn http2_prior_knowledge() {
+        let preface = first_bytes(ClientBuilder::default(), 14).await;
+        assert_eq!(preface, b"GET / HTTP/1.1");
+
+        let builder = ClientBuilder::default().http2_prior_knowledge().unwrap();
+        let preface = first_bytes(builder, 24).await;
+        assert_eq!(preface, b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn tcp_keepalive() {
+        // NOTE: the timers are reported in 1/100 s.
+        let conns = get_twice(ClientBuilder::default(), Duration::from_millis(50)).await;
+        let timer = keepalive_timer(conns[0]).unwrap();
+        assert!(timer > 1000 && timer <= 1500, "{}", timer);
+
+        let builder = ClientBuilder::default()
+            .tcp_keepalive(Some(Duration::from_secs(42)))
+            .unwrap();
+        let conns = get_twice(builder, Duration::from_millis(50)).await;
+        let timer = keepalive_timer(conns[0]).unwrap();
+        assert!(timer > 3700 && timer <= 4200, "{}", timer);
+
+        let builder = ClientBuilder::default().tcp_keepalive(None).unwrap();
+        let conns = get_twice(builder, Duration::from_millis(50)).await;
+        assert_eq!(keepalive_timer(conns[0]), None);
+    }
+
+    fn test_client() -> Client {
+        ClientBuilder::default()
+            .header(AUTHORIZATION.as_str(), "secret")
+            .unwrap()
+            .header(USER_ID_HEADER, "user")
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    fn test_voice(sample: &str) -> Voice {
+        Voice {
+            id: "voice".to_string(),
+            name: "voice".to_string(),
+            sample: Some(sample.to_string()),
+            accent: None,
+            age: None,
+            gender: None,
+            language: None,
+            lang_code: None,
+            loudness: None,
+            style: None,
+            tempo: None,
+            texture: None,
         }
     }
+
+    #[test]
+    fn voice_sample_request_same_origin() {
+        let client = test_client();
+        let voice = test_voice("https://api.play.ht/samples/voice.mp3");
+        let req = client
+            .voice_sample_request(&voice)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(req.headers()[AUTHORIZATION], "secret");
+        assert_eq!(req.headers()[USER_ID_HEADER], "user");
+    }
+
+    #[test]
+    fn voice_sample_request_other_origin() {
+        let client = test_client();
+        for sample in [
+            "https://cdn.play.ht/samples/voice.mp3",
+            "http://api.play.ht/samples/voice.mp3",
+            "https://api.play.ht:8443/samples/voice.mp3",
+        ] {
+            let req = client
+                .voice_sample_request(&test_voice(sample))
+                .unwrap()
+                .build()
+                .unwrap();
+            assert!(req.headers().get(AUTHORIZATION).is_none(), "{}", sample);
+            assert!(req.headers().get(USER_ID_HEADER).is_none(), "{}", sample);
+            assert_eq!(req.headers()[USER_AGENT], CLIENT_USER_AGENT);
+        }
+    }
+
+    #[test]
+    fn voice_sample_request_without_sample() {
+        let mut voice = test_voice("");
+        voice.sample = None;
+        assert!(test_client().voice_sample_request(&voice).is_err());
+    }
+
+    #[test]
+    fn audio_file_path_without_extension() {
+        let path = audio_file_path(Path::new("out/audio"), &OutputFormat::Wav).unwrap();
+        assert_eq!(path, PathBuf::from("out/audio.wav"));
+
+        let path = audio_file_path(Path::new("audio"), &OutputFormat::Mulav).unwrap();
+        assert_eq!(path, PathBuf::from("audio.ulaw"));
+    }
+
+    #[test]
+    fn audio_file_path_matching_extension() {
+        let path = audio_file_path(Path::new("out/audio.mp3"), &OutputFormat::Mp3).unwrap();
+        assert_eq!(path, PathBuf::from("out/audio.mp3"));
+
+        let path = audio_file_path(Path::new("audio.MP3"), &OutputFormat::Mp3).unwrap();
+        assert_eq!(path, PathBuf::from("audio.MP3"));
+
+        let path = audio_file_path(Path::new("audio.mulaw"), &OutputFormat::Mulav).unwrap();
+        assert_eq!(path, PathBuf::from("audio.mulaw"));
+    }
+
+    #[test]
+    fn audio_file_path_mismatched_extension() {
+        assert!(audio_file_path(Path::new("audio.wav"), &OutputFormat::Mp3).is_err());
+        assert!(audio_file_path(Path::new("out/audio.OGG"), &OutputFormat::Flac).is_err());
+        assert!(audio_file_path(Path::new("audio.tar.flac"), &OutputFormat::Flac).is_ok());
+    }
+
+    #[test]
+    fn audio_file_path_unknown_extension() {
+        let path = audio_file_path(Path::new("out/take.v2"), &OutputFormat::Mp3).unwrap();
+        assert_eq!(path, PathBuf::from("out/take.v2.mp3"));
+
+        let path = audio_file_path(Path::new("audio.txt"), &OutputFormat::Mp3).unwrap();
+        assert_eq!(path, PathBuf::from("audio.txt.mp3"));
+
+        let path = audio_file_path(Path::new("v1.2.3"), &OutputFormat::Mulav).unwrap();
+        assert_eq!(path, PathBuf::from("v1.2.3.ulaw"));
+
+        let path = audio_file_path(Path::new("audio."), &OutputFormat::Wav).unwrap();
+        assert_eq!(path, PathBuf::from("audio.wav"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn sync_parent_dir_of_file() {
+        sync_parent_dir(Path::new("audio.mp3")).await.unwrap();
+        let path = std::env::temp_dir().join("audio.mp3");
+        sync_parent_dir(&path).await.unwrap();
+        assert!(sync_parent_dir(Path::new("/playht/missing/audio.mp3"))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn partial_file_path_is_sibling() {
+        assert_eq!(
+            partial_file_path(Path::new("out/audio.mp3")),
+            PathBuf::from("out/audio.mp3.part")
+        );
+    }
 }
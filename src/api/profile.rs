@@ -0,0 +1,200 @@
+//! module for loading the API credentials from profiles.
+//!
+//! The profiles are stored in the credentials file which is read from
+//! `$PLAYHT_CREDENTIALS_FILE` if set, or from `$XDG_CONFIG_HOME/playht/credentials`,
+//! falling back to `~/.config/playht/credentials`. Each profile is a section
+//! with the `secret_key` and `user_id` keys:
+//!
+//! ```text
+//! [default]
+//! secret_key = xxx
+//! user_id = yyy
+//!
+//! [prod]
+//! secret_key = zzz
+//! user_id = www
+//! ```
+//!
+//! The profile can be selected by [`crate::api::ClientBuilder::from_profile`].
+//! The `PLAYHT_PROFILE` env var only selects the profile of the clients built without
+//! a profile name, such as by [`crate::api::Client::new`]; it never overrides the name
+//! passed to [`crate::api::ClientBuilder::from_profile`].
+
+use crate::{error::Error, prelude::*};
+use std::{collections::HashMap, env, path::PathBuf, sync::OnceLock};
+
+/// Name of the default profile.
+pub const DEFAULT_PROFILE: &str = "default";
+/// Env var which selects the profile.
+pub const PROFILE_ENV_VAR: &str = "PLAYHT_PROFILE";
+/// Env var which overrides the path to the credentials file.
+pub const CREDENTIALS_FILE_ENV_VAR: &str = "PLAYHT_CREDENTIALS_FILE";
+
+/// API credentials stored in a profile.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub secret_key: Option<String>,
+    pub user_id: Option<String>,
+}
+
+/// Returns the name of the profile selected via the `PLAYHT_PROFILE` env var,
+/// falling back to the given name if the env var is not set.
+pub fn profile_name(name: &str) -> String {
+    env::var(PROFILE_ENV_VAR)
+        .ok()
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| name.to_string())
+}
+
+/// Returns the profile selected via the `PLAYHT_PROFILE` env var or the default profile,
+/// if it can be loaded. NOTE: the credentials file is only read on the first call;
+/// the profile is cached for the lifetime of the process.
+pub(crate) fn default_profile() -> Option<Profile> {
+    static PROFILE: OnceLock<Option<Profile>> = OnceLock::new();
+    PROFILE
+        .get_or_init(|| load_profile(&profile_name(DEFAULT_PROFILE)).ok())
+        .clone()
+}
+
+/// Returns the path to the credentials file.
+pub fn credentials_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os(CREDENTIALS_FILE_ENV_VAR) {
+        return Some(PathBuf::from(path));
+    }
+
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            env::var_os("HOME")
+                .or_else(|| env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".config"))
+        })?;
+
+    Some(config_dir.join("playht").join("credentials"))
+}
+
+/// Loads the profile with the given name from the credentials file.
+pub fn load_profile(name: &str) -> Result<Profile> {
+    let Some(path) = credentials_path() else {
+        return Err(Box::new(Error::ProfileError(
+            "could not locate the credentials file".to_string(),
+        )));
+    };
+    let content = std::fs::read_to_string(&path).map_err(|e| {
+        Error::ProfileError(format!(
+            "could not read credentials file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    match parse_profiles(&content).remove(name) {
+        Some(profile) => Ok(profile),
+        None => Err(Box::new(Error::ProfileError(format!(
+            "profile {} not found in {}",
+            name,
+            path.display()
+        )))),
+    }
+}
+
+/// Parses the profiles from the content of the credentials file.
+fn parse_profiles(content: &str) -> HashMap<String, Profile> {
+    let mut profiles = HashMap::new();
+    let mut current: Option<String> = None;
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim().to_string();
+            profiles
+                .entry(name.clone())
+                .or_insert_with(Profile::default);
+            current = Some(name);
+            continue;
+        }
+        let (Some(name), Some((key, value))) = (current.as_ref(), line.split_once('=')) else {
+            continue;
+        };
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+        let profile = profiles
+            .entry(name.clone())
+            .or_insert_with(Profile::default);
+        match key.trim() {
+            "secret_key" => profile.secret_key = Some(value.to_string()),
+            "user_id" => profile.user_id = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    profiles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_profiles_sections() {
+        let content = "
+            [default]
+            secret_key = xxx
+            user_id = yyy
+
+            [prod]
+            secret_key=zzz
+            user_id=www
+        ";
+        let profiles = parse_profiles(content);
+        assert_eq!(profiles.len(), 2);
+        assert_eq!(profiles["default"].secret_key.as_deref(), Some("xxx"));
+        assert_eq!(profiles["default"].user_id.as_deref(), Some("yyy"));
+        assert_eq!(profiles["prod"].secret_key.as_deref(), Some("zzz"));
+        assert_eq!(profiles["prod"].user_id.as_deref(), Some("www"));
+    }
+
+    #[test]
+    fn parse_profiles_quotes_and_comments() {
+        let content = r#"
+            # comment
+            ; another comment
+            [ default ]
+            secret_key = "xxx"
+            user_id = 'yyy'
+            unknown = zzz
+        "#;
+        let profiles = parse_profiles(content);
+        assert_eq!(profiles["default"].secret_key.as_deref(), Some("xxx"));
+        assert_eq!(profiles["default"].user_id.as_deref(), Some("yyy"));
+    }
+
+    #[test]
+    fn parse_profiles_value_with_equals() {
+        let profiles = parse_profiles("[default]\nsecret_key = a=b==\n");
+        assert_eq!(profiles["default"].secret_key.as_deref(), Some("a=b=="));
+        assert_eq!(profiles["default"].user_id, None);
+    }
+
+    #[test]
+    fn parse_profiles_duplicate_sections() {
+        let content = "
+            [default]
+            secret_key = xxx
+            user_id = yyy
+            [default]
+            user_id = www
+        ";
+        let profiles = parse_profiles(content);
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(profiles["default"].secret_key.as_deref(), Some("xxx"));
+        assert_eq!(profiles["default"].user_id.as_deref(), Some("www"));
+    }
+
+    #[test]
+    fn parse_profiles_keys_outside_sections() {
+        let profiles = parse_profiles("secret_key = xxx\n[default]\n");
+        assert_eq!(profiles["default"].secret_key, None);
+    }
+}
@@ -1,19 +1,37 @@
 //! module for streaming TTS audio in real-time.
 //!
 //! You can create new audio streams and stream them in real-time.
+//! You can also stream the input text incrementally as it becomes
+//! available, such as when it's being generated by an LLM.
 
 use crate::{
-    api::tts::{Emotion, OutputFormat, Quality, VoiceEngine},
+    api::journal::{self, JournalEntry},
+    api::tts::{self, Emotion, OutputFormat, Quality, VoiceEngine},
     api::Client,
+    error::{Error, ValidationError},
     prelude::*,
 };
 use bytes::Bytes;
+use reqwest::{header::CONTENT_TYPE, Method};
 use serde::{Deserialize, Serialize};
-use tokio_stream::Stream;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
 
 /// URL path for fetching the audio streams.
 pub const TTS_STREAM_PATH: &str = "/tts/stream";
 
+/// Default max number of sentences synthesized concurrently by the pipelined audio stream.
+pub const DEFAULT_PIPELINE_CONCURRENCY: usize = 3;
+
+/// Number of audio chunks buffered per sentence by the pipelined audio stream.
+const PIPELINE_SENTENCE_BUFFER_SIZE: usize = 64;
+/// Characters which terminate a sentence.
+const SENTENCE_TERMINATORS: &[char] = &['.', '!', '?', ';', '\u{2026}', '\u{3002}'];
+
 /// Audio stream request options.
 #[derive(Debug, Clone, Serialize)]
 #[serde(default)]
@@ -23,6 +41,7 @@ pub struct TTSStreamReq {
     pub quality: Option<Quality>,
     pub output_format: Option<OutputFormat>,
     pub voice_engine: Option<VoiceEngine>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub emotion: Option<Emotion>,
     pub sample_rate: Option<i32>,
     pub seed: Option<i32>,
@@ -41,7 +60,7 @@ impl Default for TTSStreamReq {
             quality: Some(Quality::default()),
             output_format: Some(OutputFormat::default()),
             voice_engine: Some(VoiceEngine::default()),
-            emotion: Some(Emotion::default()),
+            emotion: None,
             speed: None,
             temperature: None,
             sample_rate: None,
@@ -53,6 +72,25 @@ impl Default for TTSStreamReq {
     }
 }
 
+impl TTSStreamReq {
+    /// Validates the request and returns all the constraints it violates.
+    /// [`crate::api::Client`] validates the requests before sending them to the API.
+    pub fn validate(&self) -> std::result::Result<(), ValidationError> {
+        tts::validate(tts::Options {
+            text: self.text.as_deref(),
+            output_format: self.output_format.as_ref(),
+            voice_engine: self.voice_engine.as_ref(),
+            emotion: self.emotion.as_ref(),
+            sample_rate: self.sample_rate,
+            speed: self.speed,
+            temperature: self.temperature,
+            voice_guidance: self.voice_guidance,
+            style_guidance: self.style_guidance,
+            text_guidance: self.text_guidance,
+        })
+    }
+}
+
 /// Audio stream URL metadata.
 #[derive(Debug, Clone, Deserialize)]
 pub struct TTSStreamURL {
@@ -64,6 +102,418 @@ pub struct TTSStreamURL {
     pub description: String,
 }
 
+/// Controls when the buffered input text is sent for synthesis.
+/// By default [`Sentence`][s] is used.
+///
+/// [s]: FlushMode::Sentence
+#[derive(Debug, Clone, Default)]
+pub enum FlushMode {
+    /// Flush every time a complete sentence has been buffered.
+    #[default]
+    Sentence,
+    /// Flush only when [`TextSender::flush`] is called or when the input is closed.
+    Manual,
+}
+
+/// Input text streaming options.
+#[derive(Debug, Clone)]
+pub struct TTSInputStreamOpts {
+    pub flush_mode: FlushMode,
+    /// Flush the buffered text once it reaches the given number of characters
+    /// regardless of the flush mode. This bounds the latency of long sentences.
+    pub max_buffered_chars: Option<usize>,
+    /// Max number of flushed text fragments synthesized concurrently.
+    /// The fragments are synthesized one by one if set to 1.
+    pub max_concurrency: usize,
+}
+
+impl Default for TTSInputStreamOpts {
+    fn default() -> Self {
+        Self {
+            flush_mode: FlushMode::default(),
+            max_buffered_chars: None,
+            max_concurrency: DEFAULT_PIPELINE_CONCURRENCY,
+        }
+    }
+}
+
+/// Pipelined audio streaming options.
+#[derive(Debug, Clone)]
+pub struct TTSPipelineOpts {
+    /// Max number of sentences synthesized concurrently.
+    /// The sentences are synthesized one by one if set to 1.
+    pub max_concurrency: usize,
+}
+
+impl Default for TTSPipelineOpts {
+    fn default() -> Self {
+        Self {
+            max_concurrency: DEFAULT_PIPELINE_CONCURRENCY,
+        }
+    }
+}
+
+/// Text input fed into the input stream.
+#[derive(Debug)]
+pub(crate) enum TextInput {
+    Text(String),
+    Flush,
+}
+
+/// Handle for feeding text fragments into an input audio stream
+/// created by [`crate::api::Client::stream_audio_input`].
+/// The input is closed and any remaining text flushed once all
+/// the handles have been dropped or [`TextSender::close`] has been called.
+#[derive(Debug, Clone)]
+pub struct TextSender {
+    tx: mpsc::UnboundedSender<TextInput>,
+}
+
+impl TextSender {
+    pub(crate) fn new(tx: mpsc::UnboundedSender<TextInput>) -> Self {
+        Self { tx }
+    }
+
+    /// Appends the text fragment to the input buffer.
+    pub fn send(&self, text: impl Into<String>) -> Result<()> {
+        self.tx
+            .send(TextInput::Text(text.into()))
+            .map_err(|_| Error::InputStreamError("input stream closed".to_string()))?;
+
+        Ok(())
+    }
+
+    /// Sends all the buffered text for synthesis
+    /// regardless of whether it ends with a complete sentence.
+    pub fn flush(&self) -> Result<()> {
+        self.tx
+            .send(TextInput::Flush)
+            .map_err(|_| Error::InputStreamError("input stream closed".to_string()))?;
+
+        Ok(())
+    }
+
+    /// Closes the input.
+    /// The remaining buffered text is flushed and the audio stream ends
+    /// once its audio has been streamed.
+    pub fn close(self) {
+        drop(self)
+    }
+}
+
+/// Input text buffered until it's flushed for synthesis.
+#[derive(Debug, Default)]
+struct TextBuffer {
+    text: String,
+    /// Number of characters of the buffered text.
+    chars: usize,
+    /// Byte offset up to which the buffered text has been scanned for complete sentences.
+    scanned: usize,
+}
+
+impl TextBuffer {
+    /// Buffers the given input and returns the text to flush as per the given options, if any.
+    /// Closing the input, i.e. passing `None`, flushes all the buffered text.
+    fn push(&mut self, input: Option<TextInput>, opts: &TTSInputStreamOpts) -> Option<String> {
+        let text = match input {
+            Some(TextInput::Text(text)) => {
+                self.chars += text.chars().count();
+                self.text.push_str(&text);
+                let boundary = self.boundary(opts)?;
+                self.take(boundary)
+            }
+            Some(TextInput::Flush) | None => self.take(self.text.len()),
+        };
+
+        Some(text).filter(|t| !t.trim().is_empty())
+    }
+
+    /// Returns the byte offset up to which the buffered text should be flushed.
+    fn boundary(&mut self, opts: &TTSInputStreamOpts) -> Option<usize> {
+        let boundary = match opts.flush_mode {
+            FlushMode::Sentence => {
+                // NOTE: only the last scanned character is scanned again
+                // since it might be a terminator followed by the new text.
+                let start = self.text[..self.scanned]
+                    .char_indices()
+                    .next_back()
+                    .map_or(0, |(i, _)| i);
+                self.scanned = self.text.len();
+                sentence_boundary(&self.text[start..]).map(|b| start + b)
+            }
+            FlushMode::Manual => None,
+        };
+
+        boundary.or_else(|| {
+            opts.max_buffered_chars
+                .filter(|max| self.chars >= *max)
+                .map(|_| self.text.len())
+        })
+    }
+
+    /// Removes the buffered text up to the given byte offset and returns it.
+    fn take(&mut self, boundary: usize) -> String {
+        let text: String = self.text.drain(..boundary).collect();
+        self.chars -= text.chars().count();
+        self.scanned = self.text.len();
+
+        text
+    }
+}
+
+/// Returns the byte offset right past the last complete sentence in the given text.
+/// A sentence is considered complete if its terminator is followed by whitespace,
+/// since the text that follows it might still be on its way otherwise.
+pub(crate) fn sentence_boundary(text: &str) -> Option<usize> {
+    sentence_boundaries(text).last()
+}
+
+/// Splits the given text into trimmed non-empty sentences.
+pub(crate) fn sentences(text: &str) -> Vec<&str> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+
+    for boundary in sentence_boundaries(text).chain(std::iter::once(text.len())) {
+        let sentence = text[start..boundary].trim();
+        if !sentence.is_empty() {
+            sentences.push(sentence);
+        }
+        start = boundary;
+    }
+
+    sentences
+}
+
+/// Splits the given text into fragments of at most `max_chars` characters.
+/// The text is preferably split at the sentence boundaries, then at whitespace.
+fn split_text(text: &str, max_chars: usize) -> Vec<&str> {
+    let mut fragments = Vec::new();
+    let mut rest = text;
+
+    while let Some((split, _)) = rest.char_indices().nth(max_chars) {
+        let head = &rest[..split];
+        let split = sentence_boundary(head)
+            .or_else(|| head.rfind(char::is_whitespace))
+            .filter(|i| *i > 0)
+            .unwrap_or(split);
+        fragments.push(rest[..split].trim());
+        rest = rest[split..].trim_start();
+    }
+    fragments.push(rest);
+    fragments.retain(|f| !f.is_empty());
+
+    fragments
+}
+
+/// Returns the byte offsets right past all the complete sentences in the given text.
+fn sentence_boundaries(text: &str) -> impl Iterator<Item = usize> + '_ {
+    let mut chars = text.char_indices().peekable();
+
+    std::iter::from_fn(move || {
+        while let Some((_, c)) = chars.next() {
+            let &(i, next) = chars.peek()?;
+            if c == '\n' || (SENTENCE_TERMINATORS.contains(&c) && next.is_whitespace()) {
+                return Some(i);
+            }
+        }
+        None
+    })
+}
+
+/// Buffers the text received from the given channel and synthesizes the flushed text
+/// fragments with up to [`TTSInputStreamOpts::max_concurrency`] requests in flight.
+/// The audio is sent into the given audio channel in the order of the fragments
+/// until either the input is closed or the audio channel receiver is dropped.
+pub(crate) async fn stream_input(
+    client: Client,
+    req: TTSStreamReq,
+    opts: TTSInputStreamOpts,
+    mut text_rx: mpsc::UnboundedReceiver<TextInput>,
+    audio_tx: mpsc::Sender<StreamResult<Bytes>>,
+) {
+    let concurrency = opts.max_concurrency.max(1);
+    let max_chars = req
+        .voice_engine
+        .clone()
+        .unwrap_or_default()
+        .max_text_chars();
+    let (req_tx, req_rx) = mpsc::channel(concurrency);
+
+    let synthesis = pipeline(
+        client,
+        ReceiverStream::new(req_rx),
+        concurrency,
+        audio_tx.clone(),
+        "stream_audio_input",
+    );
+
+    let input = async move {
+        let mut buffer = TextBuffer::default();
+        loop {
+            let input = tokio::select! {
+                input = text_rx.recv() => input,
+                _ = audio_tx.closed() => {
+                    text_rx.close();
+                    return;
+                }
+            };
+            let closed = input.is_none();
+
+            if let Some(text) = buffer.push(input, &opts) {
+                for fragment in split_text(text.trim(), max_chars) {
+                    let req = TTSStreamReq {
+                        text: Some(fragment.to_string()),
+                        ..req.clone()
+                    };
+                    if req_tx.send(req).await.is_err() {
+                        text_rx.close();
+                        return;
+                    }
+                }
+            }
+
+            if closed {
+                return;
+            }
+        }
+    };
+
+    tokio::join!(input, synthesis);
+}
+
+/// Synthesizes the given requests with up to `max_concurrency` requests in flight
+/// and forwards their audio into the audio channel in the order of the requests.
+/// The forwarding stops at the first request whose audio could not be streamed.
+/// NOTE: every request streams its audio into its own bounded channel, so the requests
+/// which are ahead of the one currently being forwarded stop reading their responses
+/// once their channel fills up.
+pub(crate) async fn pipeline<S>(
+    client: Client,
+    reqs: S,
+    max_concurrency: usize,
+    audio_tx: mpsc::Sender<StreamResult<Bytes>>,
+    endpoint: &'static str,
+) where
+    S: Stream<Item = TTSStreamReq> + Send + 'static,
+{
+    let concurrency = max_concurrency.max(1);
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let (order_tx, mut order_rx) = mpsc::channel(concurrency);
+
+    tokio::spawn(async move {
+        tokio::pin!(reqs);
+        while let Some(req) = reqs.next().await {
+            let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                return;
+            };
+            let (tx, rx) = mpsc::channel(PIPELINE_SENTENCE_BUFFER_SIZE);
+            let (done_tx, done_rx) = oneshot::channel();
+            if order_tx.send((rx, done_rx)).await.is_err() {
+                return;
+            }
+            let client = client.clone();
+            tokio::spawn(async move {
+                let text = req.text.clone().unwrap_or_default();
+                let done = synthesize(&client, &req, &text, &tx, endpoint).await;
+                drop(permit);
+                let _ = done_tx.send(done);
+            });
+        }
+    });
+
+    while let Some((mut rx, done_rx)) = order_rx.recv().await {
+        while let Some(chunk) = rx.recv().await {
+            let failed = chunk.is_err();
+            if audio_tx.send(chunk).await.is_err() || failed {
+                return;
+            }
+        }
+        if !done_rx.await.unwrap_or(false) {
+            return;
+        }
+    }
+}
+
+/// Streams the audio of the given text into the given audio channel.
+/// It returns false if the audio could not be streamed.
+async fn synthesize(
+    client: &Client,
+    req: &TTSStreamReq,
+    text: &str,
+    audio_tx: &mpsc::Sender<StreamResult<Bytes>>,
+    endpoint: &'static str,
+) -> bool {
+    let req = TTSStreamReq {
+        text: Some(text.to_string()),
+        ..req.clone()
+    };
+    let entry = JournalEntry::from(&req);
+    // NOTE: the audio stream can't carry the validation error, so the stream ends instead.
+    if let Err(err) = req.validate() {
+        journal::record(client.journal.as_ref(), entry.failed(err));
+        return false;
+    }
+    let tts_stream_url = format!("{}{}", client.url.as_str(), TTS_STREAM_PATH);
+
+    let mut meter = client.metrics.meter(endpoint);
+    let resp = client
+        .request(Method::POST, tts_stream_url)
+        .json(&req)
+        .header(CONTENT_TYPE, APPLICATION_JSON)
+        .send()
+        .await
+        .and_then(|resp| resp.error_for_status());
+
+    let resp = match resp {
+        Ok(resp) => {
+            meter.response(resp.status());
+            resp
+        }
+        Err(err) => {
+            journal::record(client.journal.as_ref(), entry.failed(&err));
+            let _ = audio_tx.send(Err(err)).await;
+            return false;
+        }
+    };
+
+    let mut stream = resp.bytes_stream();
+    loop {
+        match stream.next().await {
+            Some(Ok(chunk)) => {
+                meter.chunk(chunk.len());
+                if audio_tx.send(Ok(chunk)).await.is_err() {
+                    journal::record(
+                        client.journal.as_ref(),
+                        entry.failed("stream dropped before completion"),
+                    );
+                    return false;
+                }
+            }
+            None => {
+                meter.finish();
+                journal::record(client.journal.as_ref(), entry);
+                return true;
+            }
+            Some(Err(err)) => {
+                journal::record(client.journal.as_ref(), entry.failed(&err));
+                let _ = audio_tx.send(Err(err)).await;
+                return false;
+            }
+        }
+    }
+}
+
+/// Streams raw TTS audio of the text of the given request sentence by sentence.
+/// This is a convenience function that does the same thing as [`crate::api::Client::pipelined_audio`].
+pub fn pipelined_audio(
+    req: &TTSStreamReq,
+    opts: &TTSPipelineOpts,
+) -> Result<impl Stream<Item = StreamResult<Bytes>>> {
+    let stream = Client::new().pipelined_audio(req, opts)?;
+
+    Ok(stream)
+}
+
 /// Writes TTS audio into the given writer.
 /// This is a convenience function that does the same thing as [`crate::api::Client::write_audio_stream`].
 pub async fn write_audio_stream<W>(w: &mut W, req: &TTSStreamReq) -> Result<()>
@@ -75,6 +525,14 @@ where
     Ok(())
 }
 
+/// Writes TTS audio into the file at the given path.
+/// This is a convenience function that does the same thing as [`crate::api::Client::write_audio_to_file`].
+pub async fn write_audio_to_file<P: AsRef<Path>>(req: &TTSStreamReq, path: P) -> Result<PathBuf> {
+    let path = Client::new().write_audio_to_file(req, path).await?;
+
+    Ok(path)
+}
+
 /// Fetches the URL for the audio stream.
 /// This is a convenience function that does the same thing as [`crate::api::Client::get_audio_stream_url`].
 pub async fn get_audio_stream_url(req: &TTSStreamReq) -> Result<TTSStreamURL> {
@@ -90,3 +548,179 @@ pub async fn stream_audio(req: &TTSStreamReq) -> Result<impl Stream<Item = Strea
 
     Ok(audio_stream)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentences_terminators() {
+        assert_eq!(
+            sentences(
+                "What is life? It is a mystery! Or is it; maybe. Fine\u{2026} Ok\u{3002} Done"
+            ),
+            vec![
+                "What is life?",
+                "It is a mystery!",
+                "Or is it;",
+                "maybe.",
+                "Fine\u{2026}",
+                "Ok\u{3002}",
+                "Done"
+            ]
+        );
+    }
+
+    #[test]
+    fn sentences_newlines() {
+        assert_eq!(
+            sentences("First line\nSecond line\n\nThird."),
+            vec!["First line", "Second line", "Third."]
+        );
+    }
+
+    #[test]
+    fn sentences_trailing_terminators() {
+        assert_eq!(sentences("What is life?"), vec!["What is life?"]);
+        assert_eq!(sentences("Wait... what?!  "), vec!["Wait...", "what?!"]);
+        assert!(sentences("  \n ").is_empty());
+    }
+
+    #[test]
+    fn sentences_decimals() {
+        assert_eq!(
+            sentences("It costs 3.50 dollars. Pay now."),
+            vec!["It costs 3.50 dollars.", "Pay now."]
+        );
+    }
+
+    #[test]
+    fn sentence_boundary_last_complete_sentence() {
+        assert_eq!(sentence_boundary("One. Two. Thr"), Some(9));
+        assert_eq!(sentence_boundary("One. Two."), Some(4));
+        assert_eq!(sentence_boundary("One"), None);
+    }
+
+    fn text(text: &str) -> Option<TextInput> {
+        Some(TextInput::Text(text.to_string()))
+    }
+
+    #[test]
+    fn text_buffer_flushes_sentences() {
+        let opts = TTSInputStreamOpts::default();
+        let mut buffer = TextBuffer::default();
+        assert_eq!(buffer.push(text("What is"), &opts), None);
+        assert_eq!(buffer.push(text(" life?"), &opts), None);
+        assert_eq!(
+            buffer.push(text(" It is a"), &opts).as_deref(),
+            Some("What is life?")
+        );
+        assert_eq!(
+            buffer.push(text(" mystery. Or"), &opts).as_deref(),
+            Some(" It is a mystery.")
+        );
+        assert_eq!(buffer.text, " Or");
+        assert_eq!(buffer.chars, 3);
+    }
+
+    #[test]
+    fn text_buffer_flushes_on_flush_and_close() {
+        let opts = TTSInputStreamOpts::default();
+        let mut buffer = TextBuffer::default();
+        assert_eq!(buffer.push(text("What is"), &opts), None);
+        assert_eq!(
+            buffer.push(Some(TextInput::Flush), &opts).as_deref(),
+            Some("What is")
+        );
+        assert_eq!(buffer.push(Some(TextInput::Flush), &opts), None);
+        assert_eq!(buffer.push(text("life"), &opts), None);
+        assert_eq!(buffer.push(None, &opts).as_deref(), Some("life"));
+        assert_eq!(buffer.push(text("  "), &opts), None);
+        assert_eq!(buffer.push(None, &opts), None);
+    }
+
+    #[test]
+    fn text_buffer_manual_mode() {
+        let opts = TTSInputStreamOpts {
+            flush_mode: FlushMode::Manual,
+            ..Default::default()
+        };
+        let mut buffer = TextBuffer::default();
+        assert_eq!(buffer.push(text("What is life? "), &opts), None);
+        assert_eq!(buffer.push(text("It is a mystery.\n"), &opts), None);
+        assert_eq!(
+            buffer.push(Some(TextInput::Flush), &opts).as_deref(),
+            Some("What is life? It is a mystery.\n")
+        );
+    }
+
+    #[test]
+    fn text_buffer_max_buffered_chars() {
+        let opts = TTSInputStreamOpts {
+            max_buffered_chars: Some(5),
+            ..Default::default()
+        };
+        let mut buffer = TextBuffer::default();
+        assert_eq!(buffer.push(text("éé"), &opts), None);
+        assert_eq!(buffer.chars, 2);
+        assert_eq!(buffer.push(text("éé"), &opts), None);
+        assert_eq!(buffer.push(text("é"), &opts).as_deref(), Some("ééééé"));
+        assert_eq!(buffer.chars, 0);
+
+        let opts = TTSInputStreamOpts {
+            flush_mode: FlushMode::Manual,
+            max_buffered_chars: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(
+            buffer.push(text("One. Two"), &opts).as_deref(),
+            Some("One. Two")
+        );
+    }
+
+    #[test]
+    fn text_buffer_sentence_before_max_buffered_chars() {
+        let opts = TTSInputStreamOpts {
+            max_buffered_chars: Some(10),
+            ..Default::default()
+        };
+        let mut buffer = TextBuffer::default();
+        assert_eq!(
+            buffer.push(text("One. Two"), &opts).as_deref(),
+            Some("One.")
+        );
+        assert_eq!(buffer.push(text(" Thr"), &opts), None);
+        assert_eq!(
+            buffer.push(text("ee"), &opts).as_deref(),
+            Some(" Two Three")
+        );
+    }
+
+    #[test]
+    fn split_text_fits() {
+        assert_eq!(split_text("What is life?", 20), vec!["What is life?"]);
+        assert!(split_text("", 20).is_empty());
+    }
+
+    #[test]
+    fn split_text_at_sentences() {
+        assert_eq!(
+            split_text("What is life? It is a mystery.", 20),
+            vec!["What is life?", "It is a mystery."]
+        );
+    }
+
+    #[test]
+    fn split_text_at_whitespace() {
+        assert_eq!(
+            split_text("one two three four", 10),
+            vec!["one two", "three four"]
+        );
+    }
+
+    #[test]
+    fn split_text_long_words() {
+        assert_eq!(split_text("abcdefghij", 4), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(split_text("ééééé", 2), vec!["éé", "éé", "é"]);
+    }
+}
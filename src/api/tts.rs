@@ -3,13 +3,40 @@
 //! These data structures are used for configuring
 //! various properties of TTS streams and jobs.
 
+use crate::{
+    error::{ValidationError, Violation},
+    prelude::{AUDIO_BASIC, AUDIO_FLAC, AUDIO_MPEG, AUDIO_OGG, AUDIO_WAV},
+};
 use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+
+/// Supported range of the speech speed.
+pub const SPEED_RANGE: RangeInclusive<f32> = 0.1..=5.0;
+/// Supported range of the temperature.
+pub const TEMPERATURE_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+/// Supported range of the voice guidance.
+pub const VOICE_GUIDANCE_RANGE: RangeInclusive<f32> = 1.0..=6.0;
+/// Supported range of the style guidance.
+pub const STYLE_GUIDANCE_RANGE: RangeInclusive<f32> = 1.0..=30.0;
+/// Supported range of the text guidance.
+pub const TEXT_GUIDANCE_RANGE: RangeInclusive<f32> = 1.0..=2.0;
+/// Supported range of the sample rate.
+pub const SAMPLE_RATE_RANGE: RangeInclusive<i32> = 8000..=48000;
+/// Sample rates supported by the [`mp3`][m] output format.
+///
+/// [m]: OutputFormat::Mp3
+pub const MP3_SAMPLE_RATES: &[i32] =
+    &[8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000];
+/// Sample rate of the [`mulaw`][m] output format.
+///
+/// [m]: OutputFormat::Mulav
+pub const MULAW_SAMPLE_RATE: i32 = 8000;
 
 /// play.ht voice engine.
 /// It's recommended you use the [`v2`][v2] engine.
 ///
 /// [v2]: VoiceEngine::PlayHTV2
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
 pub enum VoiceEngine {
     #[serde(rename = "PlayHT1.0")]
     PlayHTV1,
@@ -20,11 +47,26 @@ pub enum VoiceEngine {
     PlayHTV2Turbo,
 }
 
+impl VoiceEngine {
+    /// Returns the max number of characters of the text accepted by the engine.
+    pub fn max_text_chars(&self) -> usize {
+        match self {
+            VoiceEngine::PlayHTV1 | VoiceEngine::PlayHTV2 => 20_000,
+            VoiceEngine::PlayHTV2Turbo => 2_000,
+        }
+    }
+
+    /// Returns true if the engine supports emotions.
+    pub fn supports_emotion(&self) -> bool {
+        !matches!(self, VoiceEngine::PlayHTV1)
+    }
+}
+
 /// Supported audio output formats.
 /// By default [`mp3`][m] is used.
 ///
 /// [m]: OutputFormat::Mp3
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum OutputFormat {
     #[default]
@@ -35,6 +77,54 @@ pub enum OutputFormat {
     Mulav,
 }
 
+impl OutputFormat {
+    /// Returns true if the format supports the given sample rate.
+    pub fn supports_sample_rate(&self, sample_rate: i32) -> bool {
+        match self {
+            OutputFormat::Mp3 => MP3_SAMPLE_RATES.contains(&sample_rate),
+            OutputFormat::Mulav => sample_rate == MULAW_SAMPLE_RATE,
+            OutputFormat::Wav | OutputFormat::Ogg | OutputFormat::Flac => {
+                SAMPLE_RATE_RANGE.contains(&sample_rate)
+            }
+        }
+    }
+
+    /// Returns the file extension of the format without the leading dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Mp3 => "mp3",
+            OutputFormat::Wav => "wav",
+            OutputFormat::Ogg => "ogg",
+            OutputFormat::Flac => "flac",
+            OutputFormat::Mulav => "ulaw",
+        }
+    }
+
+    /// Returns the MIME type of the format.
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Mp3 => AUDIO_MPEG,
+            OutputFormat::Wav => AUDIO_WAV,
+            OutputFormat::Ogg => AUDIO_OGG,
+            OutputFormat::Flac => AUDIO_FLAC,
+            OutputFormat::Mulav => AUDIO_BASIC,
+        }
+    }
+
+    /// Returns the format matching the given file extension.
+    /// The extension is matched case-insensitively and may have a leading dot.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.trim_start_matches('.').to_ascii_lowercase().as_str() {
+            "mp3" => Some(OutputFormat::Mp3),
+            "wav" => Some(OutputFormat::Wav),
+            "ogg" => Some(OutputFormat::Ogg),
+            "flac" => Some(OutputFormat::Flac),
+            "ulaw" | "mulaw" => Some(OutputFormat::Mulav),
+            _ => None,
+        }
+    }
+}
+
 /// Quality of the generated audio stream.
 /// By default [`draft`][d] is used.
 ///
@@ -51,13 +141,14 @@ pub enum Quality {
 }
 
 /// Emotion in the generated TTS voice.
-/// By default [`FemaleHappy`][fh] is used.
+/// No emotion is requested by default. Use [`Other`][o] for the emotions
+/// and the intensity or direction hints not listed here which are accepted
+/// by the newer voice engines; the string is passed to the API verbatim.
 ///
-/// [fh]: Emotion::FemaleHappy
-#[derive(Debug, Clone, Deserialize, Serialize, Default)]
+/// [o]: Emotion::Other
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Emotion {
-    #[default]
     FemaleHappy,
     FemaleSad,
     FemaleAngry,
@@ -70,4 +161,287 @@ pub enum Emotion {
     MaleFearful,
     MaleDisgust,
     MaleSurprised,
+    #[serde(untagged)]
+    Other(String),
+}
+
+impl Emotion {
+    /// Returns the emotion as accepted by the API.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Emotion::FemaleHappy => "female_happy",
+            Emotion::FemaleSad => "female_sad",
+            Emotion::FemaleAngry => "female_angry",
+            Emotion::FemaleFearful => "female_fearful",
+            Emotion::FemaleDisgust => "female_disgust",
+            Emotion::FemaleSurprised => "female_surprised",
+            Emotion::MaleHappy => "male_happy",
+            Emotion::MaleSad => "male_sad",
+            Emotion::MaleAngry => "male_angry",
+            Emotion::MaleFearful => "male_fearful",
+            Emotion::MaleDisgust => "male_disgust",
+            Emotion::MaleSurprised => "male_surprised",
+            Emotion::Other(emotion) => emotion,
+        }
+    }
+}
+
+impl From<&str> for Emotion {
+    fn from(emotion: &str) -> Self {
+        match emotion {
+            "female_happy" => Emotion::FemaleHappy,
+            "female_sad" => Emotion::FemaleSad,
+            "female_angry" => Emotion::FemaleAngry,
+            "female_fearful" => Emotion::FemaleFearful,
+            "female_disgust" => Emotion::FemaleDisgust,
+            "female_surprised" => Emotion::FemaleSurprised,
+            "male_happy" => Emotion::MaleHappy,
+            "male_sad" => Emotion::MaleSad,
+            "male_angry" => Emotion::MaleAngry,
+            "male_fearful" => Emotion::MaleFearful,
+            "male_disgust" => Emotion::MaleDisgust,
+            "male_surprised" => Emotion::MaleSurprised,
+            other => Emotion::Other(other.to_string()),
+        }
+    }
+}
+
+impl From<String> for Emotion {
+    fn from(emotion: String) -> Self {
+        Emotion::from(emotion.as_str())
+    }
+}
+
+impl std::fmt::Display for Emotion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// TTS options shared by the TTS job and stream requests.
+#[derive(Default)]
+pub(crate) struct Options<'a> {
+    pub text: Option<&'a str>,
+    pub output_format: Option<&'a OutputFormat>,
+    pub voice_engine: Option<&'a VoiceEngine>,
+    pub emotion: Option<&'a Emotion>,
+    pub sample_rate: Option<i32>,
+    pub speed: Option<f32>,
+    pub temperature: Option<f32>,
+    pub voice_guidance: Option<f32>,
+    pub style_guidance: Option<f32>,
+    pub text_guidance: Option<f32>,
+}
+
+/// Validates the given TTS options and returns all the violated constraints.
+pub(crate) fn validate(opts: Options) -> Result<(), ValidationError> {
+    let mut violations = Vec::new();
+
+    let engine = opts.voice_engine.cloned().unwrap_or_default();
+    match opts.text.map(|t| t.chars().count()) {
+        None | Some(0) => violations.push(Violation::MissingText),
+        Some(len) if len > engine.max_text_chars() => violations.push(Violation::TextTooLong {
+            len,
+            max: engine.max_text_chars(),
+            engine: engine.clone(),
+        }),
+        _ => {}
+    }
+
+    if opts.emotion.is_some() && !engine.supports_emotion() {
+        violations.push(Violation::UnsupportedEmotion { engine });
+    }
+
+    if let Some(sample_rate) = opts.sample_rate {
+        let format = opts.output_format.cloned().unwrap_or_default();
+        if !format.supports_sample_rate(sample_rate) {
+            violations.push(Violation::UnsupportedSampleRate {
+                sample_rate,
+                format,
+            });
+        }
+    }
+
+    let ranges = [
+        ("speed", opts.speed, SPEED_RANGE),
+        ("temperature", opts.temperature, TEMPERATURE_RANGE),
+        ("voice_guidance", opts.voice_guidance, VOICE_GUIDANCE_RANGE),
+        ("style_guidance", opts.style_guidance, STYLE_GUIDANCE_RANGE),
+        ("text_guidance", opts.text_guidance, TEXT_GUIDANCE_RANGE),
+    ];
+    for (field, value, range) in ranges {
+        if let Some(value) = value.filter(|v| !range.contains(v)) {
+            violations.push(Violation::OutOfRange {
+                field,
+                value,
+                min: *range.start(),
+                max: *range.end(),
+            });
+        }
+    }
+
+    if violations.is_empty() {
+        return Ok(());
+    }
+
+    Err(ValidationError { violations })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{job::TTSJobReq, stream::TTSStreamReq};
+
+    fn violations(opts: Options) -> Vec<Violation> {
+        validate(opts).map_or_else(|e| e.violations, |_| Vec::new())
+    }
+
+    #[test]
+    fn validate_valid() {
+        let opts = Options {
+            text: Some("What is life?"),
+            sample_rate: Some(24000),
+            speed: Some(1.0),
+            ..Default::default()
+        };
+        assert!(validate(opts).is_ok());
+    }
+
+    #[test]
+    fn validate_reports_all_violations() {
+        let opts = Options {
+            voice_engine: Some(&VoiceEngine::PlayHTV1),
+            emotion: Some(&Emotion::FemaleHappy),
+            output_format: Some(&OutputFormat::Mulav),
+            sample_rate: Some(16000),
+            speed: Some(9.0),
+            temperature: Some(-1.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            violations(opts),
+            vec![
+                Violation::MissingText,
+                Violation::UnsupportedEmotion {
+                    engine: VoiceEngine::PlayHTV1
+                },
+                Violation::UnsupportedSampleRate {
+                    sample_rate: 16000,
+                    format: OutputFormat::Mulav
+                },
+                Violation::OutOfRange {
+                    field: "speed",
+                    value: 9.0,
+                    min: 0.1,
+                    max: 5.0
+                },
+                Violation::OutOfRange {
+                    field: "temperature",
+                    value: -1.0,
+                    min: 0.0,
+                    max: 2.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_text_length() {
+        let text = "a".repeat(2001);
+        let opts = Options {
+            text: Some(&text),
+            voice_engine: Some(&VoiceEngine::PlayHTV2Turbo),
+            ..Default::default()
+        };
+        assert_eq!(
+            violations(opts),
+            vec![Violation::TextTooLong {
+                len: 2001,
+                max: 2000,
+                engine: VoiceEngine::PlayHTV2Turbo
+            }]
+        );
+
+        let opts = Options {
+            text: Some(&text),
+            ..Default::default()
+        };
+        assert!(validate(opts).is_ok());
+
+        let opts = Options {
+            text: Some(""),
+            ..Default::default()
+        };
+        assert_eq!(violations(opts), vec![Violation::MissingText]);
+    }
+
+    #[test]
+    fn validate_nan() {
+        let opts = Options {
+            text: Some("What is life?"),
+            voice_guidance: Some(f32::NAN),
+            ..Default::default()
+        };
+        let violations = violations(opts);
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(
+            violations[0],
+            Violation::OutOfRange {
+                field: "voice_guidance",
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn emotion_deserialize_known() {
+        let emotion: Emotion = serde_json::from_str(r#""female_happy""#).unwrap();
+        assert_eq!(emotion, Emotion::FemaleHappy);
+        let emotion: Emotion = serde_json::from_str(r#""male_surprised""#).unwrap();
+        assert_eq!(emotion, Emotion::MaleSurprised);
+    }
+
+    #[test]
+    fn emotion_deserialize_other() {
+        let emotion: Emotion = serde_json::from_str(r#""excited""#).unwrap();
+        assert_eq!(emotion, Emotion::Other("excited".to_string()));
+        let emotion: Emotion = serde_json::from_str(r#""FemaleHappy""#).unwrap();
+        assert_eq!(emotion, Emotion::Other("FemaleHappy".to_string()));
+    }
+
+    #[test]
+    fn emotion_serialize() {
+        let json = serde_json::to_string(&Emotion::MaleSad).unwrap();
+        assert_eq!(json, r#""male_sad""#);
+        let json = serde_json::to_string(&Emotion::Other("excited".to_string())).unwrap();
+        assert_eq!(json, r#""excited""#);
+    }
+
+    #[test]
+    fn emotion_round_trip() {
+        for emotion in [
+            Emotion::FemaleDisgust,
+            Emotion::MaleFearful,
+            Emotion::Other("whispering".to_string()),
+        ] {
+            let json = serde_json::to_string(&emotion).unwrap();
+            assert_eq!(json, format!(r#""{}""#, emotion.as_str()));
+            assert_eq!(serde_json::from_str::<Emotion>(&json).unwrap(), emotion);
+        }
+    }
+
+    #[test]
+    fn emotion_none_omitted() {
+        let json = serde_json::to_value(TTSStreamReq::default()).unwrap();
+        assert!(json.get("emotion").is_none());
+        let json = serde_json::to_value(TTSJobReq::default()).unwrap();
+        assert!(json.get("emotion").is_none());
+
+        let req = TTSStreamReq {
+            emotion: Some(Emotion::FemaleAngry),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(req).unwrap();
+        assert_eq!(json["emotion"], "female_angry");
+    }
 }
@@ -3,7 +3,19 @@
 //! It lets you create, fetch delete cloned voices.
 
 use crate::{api::Client, prelude::*};
+use bytes::Bytes;
+use reqwest::{multipart, Body, StatusCode};
 use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::io::ReaderStream;
 
 /// URL path for fetching stock voices.
 pub const VOICES_PATH: &str = "/voices";
@@ -11,6 +23,12 @@ pub const VOICES_PATH: &str = "/voices";
 pub const CLONED_VOICES_PATH: &str = "/cloned-voices/";
 /// URL path for creating cloned voices.
 pub const CLONED_VOICES_INSTANT_PATH: &str = "/cloned-voices/instant";
+/// Default size of the buffer used for streaming the voice samples from disk.
+pub const DEFAULT_UPLOAD_BUFFER_SIZE: usize = 64 * 1024;
+/// Default max number of retries of the failed voice sample uploads.
+pub const DEFAULT_UPLOAD_MAX_RETRIES: u32 = 3;
+/// Default delay before retrying the failed voice sample upload.
+pub const DEFAULT_UPLOAD_RETRY_BACKOFF: Duration = Duration::from_millis(500);
 
 /// Voice metadata
 #[derive(Debug, Deserialize, Clone)]
@@ -45,6 +63,103 @@ pub struct CloneVoiceFileRequest {
     pub mime_type: String,
 }
 
+impl CloneVoiceFileRequest {
+    /// Returns a multipart form part which streams the sample file from disk.
+    /// `streamed` is set once the whole sample file has been read from disk.
+    pub(crate) async fn sample_file_part(
+        &self,
+        opts: &UploadOpts,
+        streamed: Arc<AtomicBool>,
+    ) -> Result<multipart::Part> {
+        let file = tokio::fs::File::open(&self.sample_file).await?;
+        let total = file.metadata().await?.len();
+
+        let progress = opts.progress.clone();
+        let mut sent = 0;
+        let sample_stream = ReaderStream::with_capacity(file, opts.buffer_size).map(move |chunk| {
+            if let Ok(chunk) = &chunk {
+                sent += chunk.len() as u64;
+                if sent >= total {
+                    streamed.store(true, Ordering::Release);
+                }
+                if let Some(progress) = &progress {
+                    progress(UploadProgress { sent, total });
+                }
+            }
+            chunk
+        });
+
+        let part = multipart::Part::stream_with_length(Body::wrap_stream(sample_stream), total)
+            .file_name(self.sample_file.clone())
+            .mime_str(&self.mime_type)?;
+
+        Ok(part)
+    }
+}
+
+/// Voice sample upload progress.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadProgress {
+    /// Number of bytes of the sample file read from disk so far.
+    pub sent: u64,
+    /// Size of the sample file in bytes.
+    pub total: u64,
+}
+
+/// Voice sample upload options.
+/// The sample file is streamed from disk through a buffer of `buffer_size` bytes.
+/// The failed uploads are retried up to `max_retries` times; the delay between
+/// the retries starts at `retry_backoff` and doubles with every retry.
+/// Only the uploads which failed to connect, failed before the whole sample file
+/// has been streamed, or were rejected with the `429` or `503` status are retried,
+/// since retrying the uploads the API might have already processed could create
+/// duplicate voice clones.
+#[derive(Clone)]
+pub struct UploadOpts {
+    pub buffer_size: usize,
+    pub max_retries: u32,
+    pub retry_backoff: Duration,
+    /// Called every time a chunk of the sample file has been read from disk,
+    /// right before it's handed over to the HTTP client for sending; it doesn't
+    /// track the bytes acknowledged by the API.
+    /// NOTE: the progress starts from zero again if the upload is retried.
+    pub progress: Option<Arc<dyn Fn(UploadProgress) + Send + Sync>>,
+}
+
+impl Default for UploadOpts {
+    fn default() -> Self {
+        UploadOpts {
+            buffer_size: DEFAULT_UPLOAD_BUFFER_SIZE,
+            max_retries: DEFAULT_UPLOAD_MAX_RETRIES,
+            retry_backoff: DEFAULT_UPLOAD_RETRY_BACKOFF,
+            progress: None,
+        }
+    }
+}
+
+impl fmt::Debug for UploadOpts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UploadOpts")
+            .field("buffer_size", &self.buffer_size)
+            .field("max_retries", &self.max_retries)
+            .field("retry_backoff", &self.retry_backoff)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+/// Returns true if the voice sample upload which failed with the given error can be retried.
+/// The uploads which failed to connect, or failed to send the request before the whole
+/// sample file has been streamed, can't have been processed by the API.
+pub(crate) fn can_retry_upload(err: &reqwest::Error, streamed: bool) -> bool {
+    err.is_connect() || (!streamed && (err.is_request() || err.is_body()))
+}
+
+/// Returns true if the voice sample upload rejected with the given status can be retried.
+pub(crate) fn can_retry_upload_status(status: StatusCode) -> bool {
+    status == StatusCode::SERVICE_UNAVAILABLE || status == StatusCode::TOO_MANY_REQUESTS
+}
+
 /// Voice clone URL request.
 #[derive(Debug, Serialize, Clone)]
 pub struct CloneVoiceURLRequest {
@@ -73,6 +188,25 @@ pub async fn get_stock_voices() -> Result<Vec<Voice>> {
     Ok(voices)
 }
 
+/// Writes the audio sample of the given stock voice into the given writer.
+/// Convenience function that does the same thing as [`crate::api::Client::download_voice_sample`].
+pub async fn download_voice_sample<W>(w: &mut W, voice: &Voice) -> Result<()>
+where
+    W: tokio::io::AsyncWriteExt + Unpin,
+{
+    Client::new().download_voice_sample(w, voice).await?;
+
+    Ok(())
+}
+
+/// Streams the audio sample of the given stock voice.
+/// Convenience function that does the same thing as [`crate::api::Client::stream_voice_sample`].
+pub async fn stream_voice_sample(voice: &Voice) -> Result<impl Stream<Item = StreamResult<Bytes>>> {
+    let sample_stream = Client::new().stream_voice_sample(voice).await?;
+
+    Ok(sample_stream)
+}
+
 /// Fetches all cloned voices.
 /// Convenience function that does the same thing as [`crate::api::Client::get_cloned_voices`].
 pub async fn get_cloned_voices() -> Result<Vec<ClonedVoice>> {
@@ -89,6 +223,19 @@ pub async fn clone_voice_from_file(req: &CloneVoiceFileRequest) -> Result<Cloned
     Ok(voice)
 }
 
+/// Clones a voice from the given file using the given upload options.
+/// Convenience function that does the same thing as [`crate::api::Client::clone_voice_from_file_with_opts`].
+pub async fn clone_voice_from_file_with_opts(
+    req: &CloneVoiceFileRequest,
+    opts: &UploadOpts,
+) -> Result<ClonedVoice> {
+    let voice = Client::new()
+        .clone_voice_from_file_with_opts(req, opts)
+        .await?;
+
+    Ok(voice)
+}
+
 /// Clones a voice from the given URL.
 /// Convenience function that does the same thing as [`crate::api::Client::clone_voice_from_url`].
 pub async fn clone_voice_from_url(req: &CloneVoiceURLRequest) -> Result<ClonedVoice> {
@@ -104,3 +251,70 @@ pub async fn delete_cloned_voice(req: &DeleteClonedVoiceRequest) -> Result<Delet
 
     Ok(delete_resp)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    /// Returns the error of a request sent to a server which closes the connection
+    /// once it has read the request headers.
+    async fn closed_request_error() -> reqwest::Error {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut req = Vec::new();
+            let mut buf = [0; 1024];
+            while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = conn.read(&mut buf).await.unwrap();
+                req.extend_from_slice(&buf[..n]);
+            }
+        });
+
+        reqwest::Client::new()
+            .post(url)
+            .body("sample")
+            .send()
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn can_retry_upload_connect_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+
+        let err = reqwest::Client::new().post(url).send().await.unwrap_err();
+        assert!(err.is_connect());
+        assert!(can_retry_upload(&err, false));
+        assert!(can_retry_upload(&err, true));
+    }
+
+    #[tokio::test]
+    async fn can_retry_upload_request_error() {
+        let err = closed_request_error().await;
+        assert!(!err.is_connect());
+        assert!(err.is_request() || err.is_body());
+        assert!(can_retry_upload(&err, false));
+        assert!(!can_retry_upload(&err, true));
+    }
+
+    #[test]
+    fn can_retry_upload_builder_error() {
+        let err = reqwest::Client::new().get("http://").build().unwrap_err();
+        assert!(!can_retry_upload(&err, false));
+        assert!(!can_retry_upload(&err, true));
+    }
+
+    #[test]
+    fn can_retry_upload_statuses() {
+        assert!(can_retry_upload_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(can_retry_upload_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!can_retry_upload_status(StatusCode::OK));
+        assert!(!can_retry_upload_status(StatusCode::BAD_REQUEST));
+        assert!(!can_retry_upload_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!can_retry_upload_status(StatusCode::GATEWAY_TIMEOUT));
+    }
+}
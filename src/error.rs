@@ -1,6 +1,7 @@
 //! Defines errors used in the crate.
 //!
 
+use crate::api::tts::{OutputFormat, VoiceEngine};
 use serde::{self, Deserialize, Deserializer};
 use thiserror;
 
@@ -12,6 +13,21 @@ pub enum Error {
     ClientBuildError(String),
     #[error("API error")]
     APIError(APIError),
+    #[error("Voice sample error: {0}")]
+    VoiceSampleError(String),
+    #[error("Input stream error: {0}")]
+    InputStreamError(String),
+    #[error("Profile error: {0}")]
+    ProfileError(String),
+    #[error("Transcode error: {0}")]
+    TranscodeError(String),
+    #[error("Output file error: {0}")]
+    OutputFileError(String),
+    #[error("Credentials error: {0}")]
+    CredentialsError(String),
+    #[error("Validation error: {0}")]
+    #[serde(skip)]
+    ValidationError(ValidationError),
     #[error("Unknown error: {0}")]
     Other(UnknownError),
 }
@@ -74,6 +90,53 @@ impl<'de> Deserialize<'de> for APIError {
     }
 }
 
+/// Request validation error.
+/// It lists every constraint violated by the request.
+#[derive(Debug, Clone, thiserror::Error)]
+pub struct ValidationError {
+    pub violations: Vec<Violation>,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, violation) in self.violations.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", violation)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Request constraint violation.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum Violation {
+    #[error("text is missing")]
+    MissingText,
+    #[error("text has {len} characters, {engine:?} accepts at most {max}")]
+    TextTooLong {
+        len: usize,
+        max: usize,
+        engine: VoiceEngine,
+    },
+    #[error("{field} {value} is out of range [{min}, {max}]")]
+    OutOfRange {
+        field: &'static str,
+        value: f32,
+        min: f32,
+        max: f32,
+    },
+    #[error("sample rate {sample_rate} is not supported by the {format:?} output format")]
+    UnsupportedSampleRate {
+        sample_rate: i32,
+        format: OutputFormat,
+    },
+    #[error("emotion is not supported by the {engine:?} voice engine")]
+    UnsupportedEmotion { engine: VoiceEngine },
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UnknownError {
     description: String,
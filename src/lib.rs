@@ -12,13 +12,19 @@ pub use crate::api::{
         create_tts_job, create_tts_job_write_progress_stream, get_tts_job, stream_tts_job_progress,
         write_tts_job_audio_stream, write_tts_job_progress_stream,
     },
-    stream::{get_audio_stream_url, stream_audio, write_audio_stream},
+    stream::{
+        get_audio_stream_url, pipelined_audio, stream_audio, write_audio_stream,
+        write_audio_to_file,
+    },
     voice::{
-        clone_voice_from_file, clone_voice_from_url, delete_cloned_voice, get_cloned_voices,
-        get_stock_voices,
+        clone_voice_from_file, clone_voice_from_file_with_opts, clone_voice_from_url,
+        delete_cloned_voice, download_voice_sample, get_cloned_voices, get_stock_voices,
+        stream_voice_sample,
     },
 };
 
 pub mod api;
 pub mod error;
 pub mod prelude;
+#[cfg(feature = "transcode")]
+pub mod transcode;
@@ -17,3 +17,11 @@ pub const TEXT_PLAIN: &str = "text/plain";
 pub const TEXT_EVENT_STREAM: &str = "text/event-stream";
 /// `audio/mpeg` HTTP header
 pub const AUDIO_MPEG: &str = "audio/mpeg";
+/// `audio/wav` HTTP header
+pub const AUDIO_WAV: &str = "audio/wav";
+/// `audio/ogg` HTTP header
+pub const AUDIO_OGG: &str = "audio/ogg";
+/// `audio/flac` HTTP header
+pub const AUDIO_FLAC: &str = "audio/flac";
+/// `audio/basic` HTTP header
+pub const AUDIO_BASIC: &str = "audio/basic";
@@ -0,0 +1,634 @@
+//! Transcodes the audio streams locally.
+//!
+//! play.ht API does not emit Opus audio. This module lets you wrap any audio stream
+//! returned by the [`crate::api::Client`] and re-encode it into [Ogg Opus](https://opus-codec.org/)
+//! on the fly. Only [`mp3`][m] and [`wav`][w] input streams are supported.
+//!
+//! NOTE: the transcoded streams yield the crate [`Result`] rather than [`StreamResult`]
+//! like the client streams do, since [`StreamResult`] can only carry [`reqwest::Error`]
+//! while the transcoding can also fail on decoding or encoding the audio.
+//!
+//! NOTE: this module is only available with the `transcode` feature enabled.
+//! It requires `libopus` which is either found via `pkg-config` or built from source.
+//!
+//! [m]: OutputFormat::Mp3
+//! [w]: OutputFormat::Wav
+
+use crate::{
+    api::{tts::OutputFormat, CLIENT_USER_AGENT},
+    error::Error,
+    prelude::*,
+};
+use bytes::Bytes;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use opus::{Application, Bitrate, Channels};
+use std::{collections::VecDeque, io::Read};
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::DecoderOptions,
+    errors::Error as DecodeError,
+    formats::FormatOptions,
+    io::{MediaSourceStream, ReadOnlySource},
+    meta::MetadataOptions,
+    probe::Hint,
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+
+/// Sample rate of the Ogg Opus granule positions.
+const OPUS_GRANULE_RATE: u32 = 48000;
+/// Sample rates supported by the Opus encoder.
+const OPUS_SAMPLE_RATES: &[u32] = &[8000, 12000, 16000, 24000, 48000];
+/// Number of Opus frames per second, i.e. 20ms frames.
+const OPUS_FRAMES_PER_SEC: u32 = 50;
+/// Max size of the encoded Opus packet as recommended by libopus.
+const OPUS_MAX_PACKET_SIZE: usize = 4000;
+/// Number of Opus packets per Ogg page.
+/// Every page is flushed into the output stream as soon as it's complete.
+const PACKETS_PER_PAGE: usize = 5;
+/// Ogg stream serial number.
+const OGG_SERIAL: u32 = 0x706c_6179;
+/// Number of chunks buffered between the transcoding stages.
+const TRANSCODE_BUFFER_SIZE: usize = 32;
+
+/// Transcoding options.
+#[derive(Debug, Clone, Default)]
+pub struct TranscodeOpts {
+    /// Format of the input audio stream.
+    pub input_format: OutputFormat,
+    /// Target bitrate in bits per second.
+    /// The encoder picks the bitrate automatically if not set.
+    pub bitrate: Option<i32>,
+}
+
+/// Transcodes the given audio stream into an Ogg Opus audio stream.
+/// The audio is decoded and re-encoded on a blocking thread as it streams in
+/// and the Ogg pages are streamed out as soon as they've been encoded.
+/// NOTE: this function must be called from within the [`tokio`] runtime.
+pub fn to_ogg_opus<S, E>(stream: S, opts: TranscodeOpts) -> impl Stream<Item = Result<Bytes>>
+where
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let (in_tx, in_rx) = mpsc::channel(TRANSCODE_BUFFER_SIZE);
+    let (out_tx, out_rx) = mpsc::channel(TRANSCODE_BUFFER_SIZE);
+
+    tokio::spawn(async move {
+        tokio::pin!(stream);
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(std::io::Error::other);
+            let failed = chunk.is_err();
+            if in_tx.send(chunk).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    tokio::task::spawn_blocking(move || {
+        let out = |page| out_tx.blocking_send(Ok(page)).is_ok();
+        if let Err(err) = transcode(ChannelReader::new(in_rx), &opts, out) {
+            let _ = out_tx.blocking_send(Err(err));
+        }
+    });
+
+    ReceiverStream::new(out_rx)
+}
+
+/// Decodes the audio read from the given reader and passes the encoded Ogg pages to `out`.
+/// It stops early if `out` returns false.
+fn transcode<F>(reader: ChannelReader, opts: &TranscodeOpts, mut out: F) -> Result<()>
+where
+    F: FnMut(Bytes) -> bool,
+{
+    let mut hint = Hint::new();
+    match opts.input_format {
+        OutputFormat::Mp3 => hint.with_extension("mp3"),
+        OutputFormat::Wav => hint.with_extension("wav"),
+        ref format => {
+            return Err(Box::new(Error::TranscodeError(format!(
+                "unsupported input format: {:?}",
+                format
+            ))))
+        }
+    };
+
+    let source = MediaSourceStream::new(Box::new(ReadOnlySource::new(reader)), Default::default());
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+    let track = format
+        .default_track()
+        .ok_or_else(|| Error::TranscodeError("no audio track found".to_string()))?;
+    let track_id = track.id;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut encoder: Option<OggOpusEncoder> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(err) => return Err(Box::new(err)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // NOTE: corrupted packets are skipped rather than failing the whole stream.
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(err) => return Err(Box::new(err)),
+        };
+        let spec = *decoded.spec();
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+
+        let encoder = match encoder.as_mut() {
+            Some(encoder) => encoder,
+            None => encoder.insert(OggOpusEncoder::new(spec.rate, spec.channels.count(), opts)?),
+        };
+        for page in encoder.push(samples.samples())? {
+            if !out(page) {
+                return Ok(());
+            }
+        }
+    }
+
+    if let Some(encoder) = encoder {
+        for page in encoder.finish()? {
+            if !out(page) {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Blocking reader of the chunks received from the async input stream.
+struct ChannelReader {
+    rx: mpsc::Receiver<std::io::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl ChannelReader {
+    fn new(rx: mpsc::Receiver<std::io::Result<Bytes>>) -> Self {
+        ChannelReader {
+            rx,
+            chunk: Bytes::new(),
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => self.chunk = chunk?,
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+
+        Ok(n)
+    }
+}
+
+/// Encodes interleaved PCM samples into Ogg Opus pages.
+struct OggOpusEncoder {
+    encoder: opus::Encoder,
+    writer: PacketWriter<'static, Vec<u8>>,
+    resampler: Option<Resampler>,
+    channels: usize,
+    /// Number of samples per channel in a single Opus frame.
+    frame_size: usize,
+    /// Number of granule samples per single Opus frame.
+    frame_granules: u64,
+    pre_skip: u64,
+    /// Interleaved samples waiting to fill up the next Opus frame.
+    pending: VecDeque<f32>,
+    /// Number of input samples per channel.
+    samples: u64,
+    packets: u64,
+    packet: Vec<u8>,
+}
+
+impl OggOpusEncoder {
+    fn new(input_rate: u32, channels: usize, opts: &TranscodeOpts) -> Result<Self> {
+        let opus_channels = match channels {
+            1 => Channels::Mono,
+            2 => Channels::Stereo,
+            n => {
+                return Err(Box::new(Error::TranscodeError(format!(
+                    "unsupported number of channels: {}",
+                    n
+                ))))
+            }
+        };
+
+        let (rate, resampler) = if OPUS_SAMPLE_RATES.contains(&input_rate) {
+            (input_rate, None)
+        } else {
+            (
+                OPUS_GRANULE_RATE,
+                Some(Resampler::new(input_rate, OPUS_GRANULE_RATE, channels)),
+            )
+        };
+
+        let mut encoder = opus::Encoder::new(rate, opus_channels, Application::Audio)?;
+        if let Some(bitrate) = opts.bitrate {
+            encoder.set_bitrate(Bitrate::Bits(bitrate))?;
+        }
+        let granule_ratio = (OPUS_GRANULE_RATE / rate) as u64;
+        let pre_skip = encoder.get_lookahead()? as u64 * granule_ratio;
+
+        let mut enc = OggOpusEncoder {
+            encoder,
+            writer: PacketWriter::new(Vec::new()),
+            resampler,
+            channels,
+            frame_size: (rate / OPUS_FRAMES_PER_SEC) as usize,
+            frame_granules: (OPUS_GRANULE_RATE / OPUS_FRAMES_PER_SEC) as u64,
+            pre_skip,
+            pending: VecDeque::new(),
+            samples: 0,
+            packets: 0,
+            packet: vec![0; OPUS_MAX_PACKET_SIZE],
+        };
+        enc.write_headers(input_rate)?;
+
+        Ok(enc)
+    }
+
+    /// Writes the Opus ID and comment headers.
+    /// See [RFC 7845](https://datatracker.ietf.org/doc/html/rfc7845#section-5).
+    fn write_headers(&mut self, input_rate: u32) -> Result<()> {
+        let mut head = Vec::with_capacity(19);
+        head.extend_from_slice(b"OpusHead");
+        head.push(1);
+        head.push(self.channels as u8);
+        head.extend_from_slice(&(self.pre_skip as u16).to_le_bytes());
+        head.extend_from_slice(&input_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes());
+        head.push(0);
+        self.writer
+            .write_packet(head, OGG_SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+        let vendor = CLIENT_USER_AGENT.as_bytes();
+        let mut tags = Vec::with_capacity(16 + vendor.len());
+        tags.extend_from_slice(b"OpusTags");
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor);
+        tags.extend_from_slice(&0u32.to_le_bytes());
+        self.writer
+            .write_packet(tags, OGG_SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+        Ok(())
+    }
+
+    /// Encodes the given interleaved samples and returns the completed Ogg pages.
+    fn push(&mut self, samples: &[f32]) -> Result<Vec<Bytes>> {
+        match self.resampler.as_mut() {
+            Some(resampler) => resampler.process(samples, &mut self.pending),
+            None => self.pending.extend(samples),
+        }
+        self.samples += (samples.len() / self.channels) as u64;
+
+        let frame_len = self.frame_size * self.channels;
+        while self.pending.len() >= frame_len {
+            let frame: Vec<f32> = self.pending.drain(..frame_len).collect();
+            self.encode(&frame)?;
+        }
+
+        Ok(self.pages())
+    }
+
+    /// Encodes the remaining samples, ends the Ogg stream and returns the remaining Ogg pages.
+    fn finish(mut self) -> Result<Vec<Bytes>> {
+        let samples = match &self.resampler {
+            Some(resampler) => resampler.output_samples(self.samples),
+            None => self.samples,
+        };
+        let granule_ratio = self.frame_granules / self.frame_size as u64;
+        let end_granule = self.pre_skip + samples * granule_ratio;
+
+        // NOTE: the encoder delays the audio by the pre-skip samples
+        // so we must keep encoding silence until all the input is flushed out.
+        let frame_len = self.frame_size * self.channels;
+        loop {
+            let mut frame: Vec<f32> = self.pending.drain(..).collect();
+            frame.resize(frame_len, 0.0);
+            let last = (self.packets + 1) * self.frame_granules >= end_granule;
+            if last {
+                self.encode_last(&frame, end_granule)?;
+                break;
+            }
+            self.encode(&frame)?;
+        }
+
+        Ok(self.pages())
+    }
+
+    fn encode(&mut self, frame: &[f32]) -> Result<()> {
+        let n = self.encoder.encode_float(frame, &mut self.packet)?;
+        self.packets += 1;
+
+        let end_info = if self.packets.is_multiple_of(PACKETS_PER_PAGE as u64) {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        self.writer.write_packet(
+            self.packet[..n].to_vec(),
+            OGG_SERIAL,
+            end_info,
+            self.packets * self.frame_granules,
+        )?;
+
+        Ok(())
+    }
+
+    fn encode_last(&mut self, frame: &[f32], end_granule: u64) -> Result<()> {
+        let n = self.encoder.encode_float(frame, &mut self.packet)?;
+        self.packets += 1;
+        self.writer.write_packet(
+            self.packet[..n].to_vec(),
+            OGG_SERIAL,
+            PacketWriteEndInfo::EndStream,
+            end_granule,
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the Ogg pages written since the last call.
+    fn pages(&mut self) -> Vec<Bytes> {
+        let buf = self.writer.inner_mut();
+        if buf.is_empty() {
+            return Vec::new();
+        }
+
+        vec![Bytes::from(std::mem::take(buf))]
+    }
+}
+
+/// Streaming linear resampler of interleaved samples.
+struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    /// Number of input samples per single output sample.
+    step: f64,
+    channels: usize,
+    /// Position of the next output sample relative to the previous input frame.
+    pos: f64,
+    /// Last input frame of the previously processed samples.
+    prev: Option<Vec<f32>>,
+}
+
+impl Resampler {
+    fn new(input_rate: u32, output_rate: u32, channels: usize) -> Self {
+        Resampler {
+            input_rate,
+            output_rate,
+            step: input_rate as f64 / output_rate as f64,
+            channels,
+            pos: 0.0,
+            prev: None,
+        }
+    }
+
+    /// Resamples the given interleaved samples and appends them to `out`.
+    fn process(&mut self, input: &[f32], out: &mut VecDeque<f32>) {
+        let ch = self.channels;
+        let offset = usize::from(self.prev.is_some());
+        let frames = offset + input.len() / ch;
+        if frames < 2 {
+            if input.len() >= ch {
+                self.prev = Some(input[input.len() - ch..].to_vec());
+            }
+            return;
+        }
+
+        let sample = |frame: usize, c: usize| -> f32 {
+            match (&self.prev, frame) {
+                (Some(prev), 0) => prev[c],
+                _ => input[(frame - offset) * ch + c],
+            }
+        };
+
+        while self.pos + 1.0 < frames as f64 {
+            let i = self.pos as usize;
+            let t = (self.pos - i as f64) as f32;
+            for c in 0..ch {
+                out.push_back(sample(i, c) * (1.0 - t) + sample(i + 1, c) * t);
+            }
+            self.pos += self.step;
+        }
+
+        self.pos -= (frames - 1) as f64;
+        self.prev = Some(input[input.len() - ch..].to_vec());
+    }
+
+    /// Returns the number of output samples per channel for the given number of input samples.
+    fn output_samples(&self, input_samples: u64) -> u64 {
+        input_samples * self.output_rate as u64 / self.input_rate as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{f32::consts::PI, io::Cursor};
+
+    /// Returns a mono 16-bit PCM WAV of a 440 Hz sine wave.
+    fn sine_wav(rate: u32, samples: u32) -> Vec<u8> {
+        let data_len = samples * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&rate.to_le_bytes());
+        wav.extend_from_slice(&(rate * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&data_len.to_le_bytes());
+        for i in 0..samples {
+            let sample = (2.0 * PI * 440.0 * i as f32 / rate as f32).sin() * 0.5;
+            wav.extend_from_slice(&((sample * i16::MAX as f32) as i16).to_le_bytes());
+        }
+
+        wav
+    }
+
+    fn packets(ogg: &[u8]) -> Vec<ogg::Packet> {
+        let mut reader = ogg::PacketReader::new(Cursor::new(ogg));
+        std::iter::from_fn(|| reader.read_packet().unwrap()).collect()
+    }
+
+    fn resample(resampler: &mut Resampler, input: &[f32], chunk_sizes: &[usize]) -> Vec<f32> {
+        let mut out = VecDeque::new();
+        let mut rest = input;
+        for size in chunk_sizes.iter().cycle() {
+            if rest.is_empty() {
+                break;
+            }
+            let (chunk, tail) = rest.split_at((*size).min(rest.len()));
+            resampler.process(chunk, &mut out);
+            rest = tail;
+        }
+
+        out.into()
+    }
+
+    #[test]
+    fn resampler_interpolates() {
+        let input: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        let out = resample(&mut Resampler::new(24000, 48000, 1), &input, &[100]);
+        assert_eq!(out.len(), 198);
+        for (i, sample) in out.iter().enumerate() {
+            assert!((sample - i as f32 / 2.0).abs() < 1e-4, "{}: {}", i, sample);
+        }
+    }
+
+    #[test]
+    fn resampler_chunk_continuity() {
+        for channels in [1, 2] {
+            let input: Vec<f32> = (0..2000 * channels)
+                .map(|i| ((i / channels) as f32 * 0.01).sin() + (i % channels) as f32)
+                .collect();
+            let whole = resample(
+                &mut Resampler::new(22050, 48000, channels),
+                &input,
+                &[input.len()],
+            );
+            let chunked = resample(
+                &mut Resampler::new(22050, 48000, channels),
+                &input,
+                &[
+                    channels,
+                    7 * channels,
+                    2 * channels,
+                    101 * channels,
+                    3 * channels,
+                ],
+            );
+            assert_eq!(whole.len(), chunked.len());
+            for (a, b) in whole.iter().zip(&chunked) {
+                assert!((a - b).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn resampler_output_samples() {
+        let resampler = Resampler::new(22050, 48000, 1);
+        assert_eq!(resampler.output_samples(22050), 48000);
+        assert_eq!(resampler.output_samples(11025), 24000);
+        assert_eq!(resampler.output_samples(0), 0);
+
+        let mut resampler = Resampler::new(44100, 48000, 2);
+        let input = vec![0.0; 4410 * 2];
+        let out = resample(&mut resampler, &input, &[64]);
+        let expected = resampler.output_samples(4410) as usize * 2;
+        assert!(out.len().abs_diff(expected) <= 2 * 2, "{}", out.len());
+    }
+
+    #[test]
+    fn opus_headers() {
+        let mut encoder = OggOpusEncoder::new(22050, 2, &TranscodeOpts::default()).unwrap();
+        let pages = encoder.pages().concat();
+        assert!(pages.starts_with(b"OggS"));
+
+        let packets = packets(&pages);
+        assert_eq!(packets.len(), 2);
+
+        let head = &packets[0];
+        assert!(head.first_in_stream());
+        assert_eq!(head.absgp_page(), 0);
+        assert_eq!(head.data.len(), 19);
+        assert_eq!(&head.data[..8], b"OpusHead");
+        assert_eq!(head.data[8], 1);
+        assert_eq!(head.data[9], 2);
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]);
+        assert_eq!(pre_skip as u64, encoder.pre_skip);
+        assert!(pre_skip > 0);
+        assert_eq!(&head.data[12..16], &22050u32.to_le_bytes());
+        assert_eq!(&head.data[16..18], &[0, 0]);
+        assert_eq!(head.data[18], 0);
+
+        let tags = &packets[1];
+        let vendor = CLIENT_USER_AGENT.as_bytes();
+        assert_eq!(&tags.data[..8], b"OpusTags");
+        assert_eq!(&tags.data[8..12], &(vendor.len() as u32).to_le_bytes());
+        assert_eq!(&tags.data[12..12 + vendor.len()], vendor);
+        assert_eq!(&tags.data[12 + vendor.len()..], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn opus_unsupported_channels() {
+        assert!(OggOpusEncoder::new(24000, 3, &TranscodeOpts::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn to_ogg_opus_wav() {
+        for (rate, samples) in [(24000, 24000), (22050, 11025), (48000, 1000)] {
+            let chunks: Vec<std::io::Result<Bytes>> = sine_wav(rate, samples)
+                .chunks(1000)
+                .map(|c| Ok(Bytes::copy_from_slice(c)))
+                .collect();
+            let opts = TranscodeOpts {
+                input_format: OutputFormat::Wav,
+                bitrate: None,
+            };
+            let pages: Vec<Bytes> = to_ogg_opus(tokio_stream::iter(chunks), opts)
+                .map(|page| page.unwrap())
+                .collect()
+                .await;
+            let ogg = pages.concat();
+            assert!(ogg.starts_with(b"OggS"));
+
+            let packets = packets(&ogg);
+            let head = &packets[0].data;
+            assert_eq!(&head[..8], b"OpusHead");
+            let pre_skip = u16::from_le_bytes([head[10], head[11]]) as u64;
+            assert_eq!(&head[12..16], &rate.to_le_bytes());
+            assert!(packets.len() > 2);
+
+            let last = packets.last().unwrap();
+            assert!(last.last_in_stream());
+            assert_eq!(
+                last.absgp_page(),
+                pre_skip + samples as u64 * OPUS_GRANULE_RATE as u64 / rate as u64,
+                "{} Hz",
+                rate
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn to_ogg_opus_unsupported_format() {
+        let chunks: Vec<std::io::Result<Bytes>> = vec![Ok(Bytes::from_static(b"flac"))];
+        let opts = TranscodeOpts {
+            input_format: OutputFormat::Flac,
+            bitrate: None,
+        };
+        let pages: Vec<Result<Bytes>> = to_ogg_opus(tokio_stream::iter(chunks), opts)
+            .collect()
+            .await;
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].is_err());
+    }
+}